@@ -1,6 +1,7 @@
 //! Api module
 //!
 
+use regex::Regex;
 #[allow(unused_imports)]
 use reqwest::Url;
 use tracing::{
@@ -15,12 +16,24 @@ use tokio::sync::{
 	oneshot,
 	Mutex
 };
+use futures_util::stream;
+use multer::Multipart;
 use uuid::Uuid;
 use warp::{
 	filters::body::BodyDeserializeError,
-	reject,
+	hyper::body::Bytes,
+	reject::{
+		self,
+		InvalidQuery,
+		LengthRequired,
+		PayloadTooLarge
+	},
 	reply,
 	http::{
+		header::{
+			HeaderName,
+			HeaderValue
+		},
 		Response,
 		StatusCode
 	},
@@ -30,29 +43,97 @@ use warp::{
 };
 
 use std::{
+	collections::{
+		HashMap,
+		HashSet
+	},
 	convert::Infallible,
 	net::{
+		IpAddr,
 		SocketAddr,
 		ToSocketAddrs
 	},
-	sync::Arc
+	sync::Arc,
+	time::Duration
 };
 
 use crate::{
 	auth::Engine,
+	checks::ContentChecks,
+	client::{
+		ProxyChoice,
+		ProxySettings,
+		TlsOptions
+	},
+	dns::HostOverrides,
+	egress::EgressPolicy,
+	documents::{
+		DocumentFormat,
+		Extracted
+	},
 	dto::{
+		self,
+		Checks,
+		DocumentQuery,
+		DocumentReply,
+		Expect,
+		JobOptions,
+		JobRequest,
+		ProxySpec,
+		RedirectChain,
 		StatusReply,
+		StatusReplyV2,
+		StatusSpec,
+		SubmissionReply,
+		UploadQuery,
+		UrlEntry,
 		LoginRequest
 	},
 	errors::{
 		EmptyRequest,
-		InvalidUrl,
+		Forbidden,
+		InvalidExpectation,
+		InvalidOption,
+		InvalidUpload,
+		InvalidUrls,
 		SyncError,
-		Unauthorized
+		TooManyUrls,
+		Unauthorized,
+		UnsupportedMediaType
+	},
+	expect::{
+		Expectations,
+		StatusMatcher
+	},
+	extras::{
+		Credentials,
+		HostScope,
+		RequestExtras
+	},
+	fetch::MAX_RETRIES,
+	links::{
+		CrawlLimits,
+		DEFAULT_MAX_DEPTH,
+		DEFAULT_MAX_PAGES
 	},
 	messages::{
+		AddressProbe,
+		CheckMode,
+		FetchOptions,
+		FetchReport,
 		RequestMessage,
-		StatusRequestMessage
+		StatusReplyMessage,
+		StatusRequestMessage,
+		Target
+	},
+	upload::{
+		self,
+		CsvLayout,
+		Format
+	},
+	validate::{
+		normalize,
+		UrlPolicy
 	}
 };
 
@@ -65,30 +146,408 @@ async fn health_check() -> Result<impl Reply, Rejection> {
 	))
 }
 
-#[tracing::instrument(level="debug")]
+fn parse_status_set(specs: &[StatusSpec]) -> Result<Vec<StatusMatcher>, String> {
+	specs.iter()
+		.map(|spec| match spec {
+			StatusSpec::Code(code) => StatusMatcher::try_from(*code),
+			StatusSpec::Class(class) => class.parse()
+		})
+		.collect()
+}
+
+fn parse_expectations(expect: &Expect) -> Result<Expectations, String> {
+	let final_url = expect.get_final_url()
+		.map(Url::parse)
+		.transpose()
+		.map_err(|e| format!("invalid final URL: {e}"))?;
+	Ok(Expectations::new(
+		parse_status_set(expect.get_status())?,
+		parse_status_set(expect.get_redirect_status())?,
+		final_url,
+		expect.get_max_latency_ms().map(Duration::from_millis)
+	))
+}
+
+fn parse_checks(checks: &Checks) -> Result<ContentChecks, String> {
+	let regex = checks.get_regex()
+		.iter()
+		.map(|re| Regex::new(re).map_err(|e| format!("invalid regex: {e}")))
+		.collect::<Result<Vec<Regex>, String>>()?;
+	let json = checks.get_json()
+		.iter()
+		.map(|(pointer, value)| if pointer.is_empty() || pointer.starts_with('/') {
+			Ok((pointer.clone(), value.clone()))
+		} else {
+			Err(format!("invalid JSON pointer \"{pointer}\""))
+		})
+		.collect::<Result<Vec<_>, String>>()?;
+	Ok(ContentChecks::new(
+		checks.get_contains().to_vec(),
+		checks.get_not_contains().to_vec(),
+		regex,
+		json,
+		checks.get_content_type().map(String::from)
+	))
+}
+
+/// Headers, cookies and credentials of the job. Each of them needs a list
+/// of hosts, or they would leak on the first redirection to another site.
+fn parse_extras(options: &JobOptions) -> Result<RequestExtras, String> {
+	let mut extras = RequestExtras::default();
+	for header in options.get_headers() {
+		if header.get_hosts().is_empty() {
+			return Err(format!("header \"{}\" needs a list of hosts", header.get_name()));
+		}
+		let name = HeaderName::from_bytes(header.get_name().as_bytes())
+			.map_err(|_| format!("invalid header name \"{}\"", header.get_name()))?;
+		HeaderValue::from_str(header.get_value().expose())
+			.map_err(|_| format!("invalid value for header \"{}\"", header.get_name()))?;
+		extras.add_header(HostScope::new(header.get_hosts()), name, header.get_value().clone());
+	}
+	for cookie in options.get_cookies() {
+		if cookie.get_hosts().is_empty() {
+			return Err(format!("cookie \"{}\" needs a list of hosts", cookie.get_name()));
+		}
+		let valid = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_graphic() && c != ';' && c != ',');
+		if !valid(cookie.get_name()) || cookie.get_name().contains('=') || !valid(cookie.get_value().expose()) {
+			return Err(format!("invalid cookie \"{}\"", cookie.get_name()));
+		}
+		extras.add_cookie(HostScope::new(cookie.get_hosts()), cookie.get_name().into(), cookie.get_value().clone());
+	}
+	for auth in options.get_auth() {
+		if auth.get_hosts().is_empty() {
+			return Err("credentials need a list of hosts".into());
+		}
+		let credentials = match (auth.get_basic(), auth.get_bearer()) {
+			(Some(basic), None) => Credentials::Basic {
+				user: basic.get_user().into(),
+				password: basic.get_password().clone()
+			},
+			(None, Some(token)) => Credentials::Bearer(token.clone()),
+			_ => return Err("credentials need exactly one of \"basic\" or \"bearer\"".into())
+		};
+		extras.add_credentials(HostScope::new(auth.get_hosts()), credentials);
+	}
+	Ok(extras)
+}
+
+fn parse_proxy(spec: Option<&ProxySpec>) -> Result<ProxyChoice, String> {
+	match spec {
+		None => Ok(ProxyChoice::Default),
+		Some(ProxySpec::Named(name)) => match name.as_str() {
+			"default" => Ok(ProxyChoice::Default),
+			"direct" => Ok(ProxyChoice::Direct),
+			_ => Err(format!("unknown proxy \"{name}\", expected \"default\", \"direct\" or an object"))
+		},
+		Some(ProxySpec::Custom { url, no_proxy }) => {
+			let no_proxy = (!no_proxy.is_empty()).then(|| no_proxy.join(","));
+			let settings = ProxySettings::new(url.clone(), no_proxy);
+			settings.validate()?;
+			Ok(ProxyChoice::Custom(settings))
+		}
+	}
+}
+
+/// Hosts pinned to addresses by the job, which have to be
+/// addresses the egress policy lets us connect to
+fn parse_overrides(options: &JobOptions, egress: &EgressPolicy) -> Result<HostOverrides, String> {
+	options.get_resolve()
+		.iter()
+		.map(|(host, addresses)| {
+			let ips = addresses.as_slice()
+				.iter()
+				.map(|ip| {
+					let ip = ip.parse::<IpAddr>()
+						.map_err(|_| format!("invalid address \"{ip}\" for host \"{host}\""))?;
+					egress.check_ip(ip)
+						.map_err(|e| format!("address {ip} for host \"{host}\" is {e}"))?;
+					Ok(ip)
+				})
+				.collect::<Result<Vec<IpAddr>, String>>()?;
+			if ips.is_empty() {
+				return Err(format!("no address for host \"{host}\""));
+			}
+			Ok((host.trim_end_matches('.').to_ascii_lowercase(), ips))
+		})
+		.collect()
+}
+
+fn fetch_options(options: &JobOptions, egress: &EgressPolicy) -> Result<FetchOptions, String> {
+	if options.get_retries() > MAX_RETRIES {
+		return Err(format!("at most {MAX_RETRIES} retries, not {}", options.get_retries()));
+	}
+	Ok(FetchOptions::default()
+		.with_redirects(options.get_follow_redirects(), options.get_max_redirects())
+		.with_retries(options.get_retries())
+		.with_soft_404(options.get_detect_soft_404())
+		.with_extras(parse_extras(options)?)
+		.with_proxy(parse_proxy(options.get_proxy())?)
+		.with_tls(TlsOptions::new(
+			options.get_insecure(),
+			options.get_client_identity().map(String::from)
+		))
+		.with_cache(!options.get_no_cache())
+		.with_conditional(options.get_conditional())
+		.with_cert_warning(options.get_cert_expiry_days())
+		.with_address_probe(if options.get_every_address() {
+			AddressProbe::Addresses
+		} else if options.get_dual_stack() {
+			AddressProbe::Families
+		} else {
+			AddressProbe::Off
+		})
+		.with_overrides(parse_overrides(options, egress)?)
+		.with_seeds(options.get_seeds(), options.get_sort_query())
+		.with_robots(!options.get_ignore_robots())
+		.with_crawl(options.get_crawl()
+			.map(|crawl| CrawlLimits::new(
+				crawl.get_max_depth().unwrap_or(DEFAULT_MAX_DEPTH),
+				crawl.get_max_pages().unwrap_or(DEFAULT_MAX_PAGES),
+				crawl.get_scope()
+			))
+			.transpose()?))
+}
+
+fn parse_list(format: Format, body: &[u8], layout: &CsvLayout) -> Result<Vec<UrlEntry>, Rejection> {
+	let text = upload::decode(body)
+		.map_err(|e| reject::custom(InvalidUpload::new(e)))?;
+	match format {
+		Format::Csv => upload::parse_csv(text, layout)
+			.map_err(|e| reject::custom(InvalidUpload::new(e))),
+		_ => Ok(upload::parse_text(text))
+	}
+}
+
+/// A file of a multipart upload: its type, its name and its contents
+type UploadedFile = (Option<String>, Option<String>, Bytes);
+
+/// Files of a multipart upload, along with the job options
+/// in a part named `options`
+async fn read_form(content_type: &str, body: Bytes) -> Result<(JobOptions, Vec<UploadedFile>), Rejection> {
+	let invalid = |e: multer::Error| reject::custom(InvalidUpload::new(e.to_string()));
+	let boundary = multer::parse_boundary(content_type).map_err(invalid)?;
+	let mut form = Multipart::new(stream::once(async move { Ok::<Bytes, Infallible>(body) }), boundary);
+	let mut options = JobOptions::default();
+	let mut files = Vec::new();
+	while let Some(field) = form.next_field().await.map_err(invalid)? {
+		let name = field.name().map(String::from);
+		let file_name = field.file_name().map(String::from);
+		let field_type = field.content_type().map(ToString::to_string);
+		let data = field.bytes().await.map_err(invalid)?;
+		if file_name.is_none() && name.as_deref() == Some("options") {
+			options = serde_json::from_slice(&data)
+				.map_err(|e| reject::custom(InvalidUpload::new(format!("invalid options: {e}"))))?;
+			continue;
+		}
+		files.push((field_type, file_name, data));
+	}
+	Ok((options, files))
+}
+
+async fn read_multipart(content_type: &str, body: Bytes, layout: &CsvLayout) -> Result<JobRequest, Rejection> {
+	let (options, files) = read_form(content_type, body).await?;
+	let mut urls = Vec::new();
+	for (file_type, file_name, data) in files {
+		let format = Format::of_file(file_type.as_deref(), file_name.as_deref())
+			.ok_or_else(|| reject::custom(UnsupportedMediaType::new(file_type.unwrap_or_default())))?;
+		urls.extend(parse_list(format, &data, layout)?);
+	}
+	Ok(JobRequest::new(urls, options))
+}
+
+/// The job in the body of a submission, read according to its type
+async fn read_submission(
+	content_type: Option<String>,
+	query: UploadQuery,
+	body: Bytes
+) -> Result<JobRequest, Rejection> {
+	let layout = CsvLayout::new(query.get_url_column(), query.get_delimiter(), query.get_header())
+		.map_err(|e| reject::custom(InvalidUpload::new(e)))?;
+	match Format::of_body(content_type.as_deref(), &body) {
+		Format::Json => serde_json::from_slice(&body)
+			.map_err(|e| reject::custom(InvalidUpload::new(format!("invalid JSON: {e}")))),
+		Format::Multipart => read_multipart(content_type.as_deref().unwrap_or_default(), body, &layout).await,
+		format => Ok(JobRequest::new(parse_list(format, &body, &layout)?, JobOptions::default()))
+	}
+}
+
+/// The type of the body, if it is JSON or not as `json` says. Bodies without
+/// a type are JSON, as `warp::body::json` has it.
+fn content_type(json: bool) -> impl Filter<Extract=(Option<String>,), Error=Rejection> + Clone {
+	warp::header::optional::<String>("content-type")
+		.and_then(move |content_type: Option<String>| async move {
+			let essence = content_type.as_deref().map(upload::essence);
+			if matches!(essence.as_deref(), None | Some("application/json")) == json {
+				Ok(content_type)
+			} else {
+				Err(reject::not_found())
+			}
+		})
+}
+
+fn submission(max_body: u64) -> impl Filter<Extract=(JobRequest,), Error=Rejection> + Clone {
+	// JSON is left to warp, so that it is refused the way it always was
+	let json = content_type(true)
+		.map(|_| ())
+		.untuple_one()
+		.and(warp::body::json());
+	let upload = content_type(false)
+		.and(warp::query::<UploadQuery>())
+		.and(warp::body::bytes())
+		.and_then(read_submission);
+	warp::body::content_length_limit(max_body)
+		.and(json.or(upload).unify())
+}
+
+/// The links of the documents in a submission, sent as the body or
+/// uploaded as files, and the job options
+async fn read_documents(
+	content_type: Option<String>,
+	query: DocumentQuery,
+	body: Bytes
+) -> Result<(Extracted, JobOptions), Rejection> {
+	let base = query.get_base()
+		.map(Url::parse)
+		.transpose()
+		.map_err(|e| reject::custom(InvalidOption::new(format!("invalid base URL: {e}"))))?;
+	let format = query.get_format()
+		.map(str::parse::<DocumentFormat>)
+		.transpose()
+		.map_err(|e| reject::custom(InvalidOption::new(e)))?;
+	let (options, documents) = match Format::of_body(content_type.as_deref(), &body) {
+		Format::Multipart => read_form(content_type.as_deref().unwrap_or_default(), body).await?,
+		_ => (JobOptions::default(), vec![(content_type, None, body)])
+	};
+	let mut extracted = Extracted::default();
+	for (document_type, file_name, data) in documents {
+		let format = format
+			.or_else(|| DocumentFormat::detect(document_type.as_deref(), file_name.as_deref(), &data))
+			.ok_or_else(|| reject::custom(UnsupportedMediaType::new(document_type.unwrap_or_default())))?;
+		let text = upload::decode(&data)
+			.map_err(|e| reject::custom(InvalidUpload::new(e)))?;
+		extracted.read(format, text, base.as_ref())
+			.map_err(|e| reject::custom(InvalidUpload::new(e)))?;
+	}
+	Ok((extracted, options))
+}
+
+fn document_submission(max_body: u64) -> impl Filter<Extract=((Extracted, JobOptions),), Error=Rejection> + Clone {
+	warp::body::content_length_limit(max_body)
+		.and(warp::header::optional::<String>("content-type"))
+		.and(warp::query::<DocumentQuery>())
+		.and(warp::body::bytes())
+		.and_then(read_documents)
+}
+
+#[tracing::instrument(level="debug", skip(url_policy))]
 async fn request_inspection(
+	admin: bool,
 	manager_tx: mpsc::Sender<RequestMessage>,
-	list: Vec<String>
-) -> Result<impl Reply, Rejection> {
+	url_policy: Arc<UrlPolicy>,
+	job: JobRequest
+) -> Result<reply::Response, Rejection> {
+	let legacy = job.is_legacy();
+	let (list, options) = job.explode();
+	let (uuid, accepted, merged) = create_job(admin, &manager_tx, &url_policy, &list, &options).await?;
+	Ok(submission_reply(legacy, uuid, accepted, merged))
+}
+
+/// Check the URLs of a job and hand it to the manager. Returns the UUID
+/// of the job, and how many URLs were accepted and merged with others.
+async fn create_job(
+	admin: bool,
+	manager_tx: &mpsc::Sender<RequestMessage>,
+	url_policy: &UrlPolicy,
+	list: &[UrlEntry],
+	options: &JobOptions
+) -> Result<(Uuid, usize, usize), Rejection> {
 	if list.is_empty() {
 		warn!("Received request with 0 URLs");
 		return Err(reject::custom(EmptyRequest));
 	}
+	if options.get_insecure() && !admin {
+		warn!("Refused insecure job from a non-admin user");
+		return Err(reject::custom(Forbidden::new("only admins can disable certificate verification".into())));
+	}
+	if list.len() > url_policy.get_max_urls() {
+		warn!("Received request with {} URLs", list.len());
+		return Err(reject::custom(TooManyUrls::new(list.len(), url_policy.get_max_urls())));
+	}
 
-	// Assert that all of them are URLs
+	// Assert that all of them are URLs we can fetch, reporting every one that is not.
+	// Entries that point to the same URL are only kept once.
+	let mut invalid = Vec::new();
+	let mut seen = HashSet::new();
+	let mut unique = Vec::new();
+	for entry in list {
+		match url_policy.validate(entry.get_url()) {
+			Ok(url) => {
+				let url = normalize(url, options.get_sort_query());
+				if seen.insert(url.clone()) {
+					unique.push((entry, url));
+				}
+			},
+			Err(reason) => {
+				warn!("Found invalid URL \"{}\" in request: {}", entry.get_url(), reason);
+				invalid.push((entry.get_url().to_string(), reason));
+			}
+		}
+	}
+	if !invalid.is_empty() {
+		return Err(reject::custom(InvalidUrls::new(invalid)));
+	}
+
+	// Expectations given in the options apply to every URL
+	// that does not come with its own
+	let default_expect = options.get_expect()
+		.map(parse_expectations)
+		.transpose()
+		.map_err(|e| reject::custom(InvalidExpectation::new("options".into(), e)))?;
+	let default_checks = options.get_checks()
+		.map(parse_checks)
+		.transpose()
+		.map_err(|e| reject::custom(InvalidExpectation::new("options".into(), e)))?
+		.map(Arc::new);
+	let default_mode = options.get_mode()
+		.map(str::parse)
+		.transpose()
+		.map_err(|e| reject::custom(InvalidOption::new(e)))?
+		.unwrap_or_default();
+
+	let merged = list.len() - unique.len();
+	if merged > 0 {
+		debug!("Merged {} duplicate URLs", merged);
+	}
 	let mut good_urls = Vec::new();
-	for maybe_url in list {
-		if let Ok(url) = Url::parse(&maybe_url) {
-			good_urls.push(url);
-		} else {
-			warn!("Found invalid URL \"{}\" in request", maybe_url);
-			return Err(reject::custom(InvalidUrl::new(maybe_url)));
+	for (entry, url) in unique {
+		let expect = match entry.get_expect() {
+			Some(expect) => Some(parse_expectations(expect)
+				.map_err(|e| reject::custom(InvalidExpectation::new(entry.get_url().into(), e)))?),
+			None => default_expect.clone()
+		};
+		let checks = match entry.get_checks() {
+			Some(checks) => Some(Arc::new(parse_checks(checks)
+				.map_err(|e| reject::custom(InvalidExpectation::new(entry.get_url().into(), e)))?)),
+			None => default_checks.clone()
+		};
+		let mode = match entry.get_mode() {
+			Some(mode) => mode.parse()
+				.map_err(|e| reject::custom(InvalidExpectation::new(entry.get_url().into(), e)))?,
+			None => default_mode
+		};
+		if entry.is_sitemap() && mode != CheckMode::Http {
+			return Err(reject::custom(InvalidExpectation::new(entry.get_url().into(), "a sitemap can only be checked in http mode".into())));
 		}
+		good_urls.push(Target::new(url, mode, expect, checks)
+			.with_sitemap(entry.is_sitemap())
+			.with_labels(entry.get_labels().cloned().unwrap_or_default()));
 	}
 
 	// Create a oneshot channel to receive the result
 	let (ret_tx, ret_rx) = oneshot::channel();
-	let reqmsg = RequestMessage::new(good_urls, ret_tx);
+	let options = fetch_options(options, url_policy.get_egress())
+		.map_err(|e| reject::custom(InvalidOption::new(e)))?;
+	let reqmsg = RequestMessage::new(good_urls, options, ret_tx);
 	manager_tx.send(reqmsg).await
 		.map_err(|e| reject::custom(
 				SyncError::from(e)
@@ -97,40 +556,112 @@ async fn request_inspection(
 	let new_uuid = ret_rx.await
 		.map_err(|e| reject::custom(
 			SyncError::from(e)
-		))?;
-	Ok(reply::with_status(
-		new_uuid.to_string(),
-		StatusCode::OK
+		))?
+		.map_err(|e| reject::custom(InvalidOption::new(e)))?;
+	Ok((new_uuid, list.len() - merged, merged))
+}
+
+#[tracing::instrument(level="debug", skip(url_policy, document))]
+async fn document_inspection(
+	admin: bool,
+	manager_tx: mpsc::Sender<RequestMessage>,
+	url_policy: Arc<UrlPolicy>,
+	document: (Extracted, JobOptions)
+) -> Result<reply::Response, Rejection> {
+	let (extracted, options) = document;
+	let (list, unparseable) = extracted.explode();
+	debug!("Extracted {} links from documents, {} unparseable", list.len(), unparseable.len());
+	if list.is_empty() && !unparseable.is_empty() {
+		return Err(reject::custom(InvalidUpload::new(format!(
+			"no link to check, and {} that could not be parsed: {}",
+			unparseable.len(),
+			unparseable.join(", ")
+		))));
+	}
+	let (uuid, accepted, merged) = create_job(admin, &manager_tx, &url_policy, &list, &options).await?;
+	Ok(reply::json(&DocumentReply::new(
+		uuid.to_string(),
+		list.len(),
+		accepted,
+		merged,
+		unparseable
+	)).into_response())
+}
+
+/// Plain lists of URLs only get the UUID of their job back
+fn submission_reply(legacy: bool, uuid: Uuid, accepted: usize, merged: usize) -> reply::Response {
+	if legacy {
+		return reply::with_status(
+			uuid.to_string(),
+			StatusCode::OK
+		).into_response();
+	}
+	reply::json(&SubmissionReply::new(
+		uuid.to_string(),
+		accepted,
+		merged
+	)).into_response()
+}
+
+fn redirect_chain(report: &FetchReport) -> Option<RedirectChain> {
+	if report.get_redirects().is_empty() {
+		return None;
+	}
+	Some(RedirectChain::new(
+		report.get_redirects()
+			.iter()
+			.map(|hop| dto::RedirectHop::new(
+				hop.get_status().as_u16(),
+				hop.get_location().to_string()
+			))
+			.collect(),
+		report.get_final_url().to_string()
 	))
 }
 
-#[tracing::instrument(level="debug")]
-async fn request_status(
+fn build_status_reply(result: StatusReplyMessage) -> StatusReply {
+	let finished = result.is_finished();
+	let mut results = HashMap::new();
+	let mut redirects = HashMap::new();
+	for (url, report) in result.into_results() {
+		if let Some(chain) = redirect_chain(&report) {
+			redirects.insert(url.to_string(), chain);
+		}
+		results.insert(url.to_string(), i32::from(report.get_result()));
+	}
+	StatusReply::new(finished, results, redirects)
+}
+
+async fn poll_status(
 	uuid: Uuid,
 	status_tx: mpsc::Sender<StatusRequestMessage>
-) -> Result<impl warp::Reply, warp::Rejection> {
-	// Status request
+) -> Result<StatusReplyMessage, Rejection> {
 	let (o_tx, o_rx) = oneshot::channel();
 	let req = (uuid, o_tx);
 	status_tx.send(req).await
 		.map_err(SyncError::from)?;
-	match o_rx.await.map_err(SyncError::from)? {
-		Some(result) => Ok({
-			reply::json(& StatusReply::new(
-					result.is_finished(),
-					result.into_results()
-						.into_iter()
-						.map(|(k,v)| {
-							(k.to_string(), i32::from(v))
-						})
-						.collect()
-			))
-		}),
-		None => {
-			warn!("Request missing UUID={}", uuid);
-			Err(reject::reject())
-		}
-	}
+	o_rx.await.map_err(SyncError::from)?.ok_or_else(|| {
+		warn!("Request missing UUID={}", uuid);
+		reject::reject()
+	})
+}
+
+#[tracing::instrument(level="debug")]
+async fn request_status(
+	uuid: Uuid,
+	status_tx: mpsc::Sender<StatusRequestMessage>
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let result = poll_status(uuid, status_tx).await?;
+	Ok(reply::json(&build_status_reply(result)))
+}
+
+#[tracing::instrument(level="debug")]
+async fn request_status_v2(
+	uuid: Uuid,
+	status_tx: mpsc::Sender<StatusRequestMessage>
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let result = poll_status(uuid, status_tx).await?;
+	Ok(reply::json(&StatusReplyV2::from(result)))
 }
 
 fn check_authentication<E: Filter<Extract=(Arc<Mutex<Engine>>,), Error=Infallible> + Clone + Send + Sync>(
@@ -155,6 +686,18 @@ async fn cookie_checker(
 	})
 }
 
+/// Whether the caller is an admin, once they are known to be authenticated
+fn check_admin<E: Filter<Extract=(Arc<Mutex<Engine>>,), Error=Infallible> + Clone + Send + Sync>(
+auth_engine: E
+) -> impl Filter<Extract=(bool,), Error=Rejection> + Clone {
+	warp::filters::cookie::optional::<String>("HEX")
+		.and(auth_engine)
+		.and_then(|cookie: Option<String>, engine: Arc<Mutex<Engine>>| async move {
+			let engine = engine.lock().await;
+			Ok::<bool, Rejection>(cookie.is_some_and(|cook| engine.is_admin(&cook)))
+		})
+}
+
 #[tracing::instrument(level="debug", skip(auth_engine, body))]
 async fn authentication_request(
 	auth_engine: Arc<Mutex<Engine>>,
@@ -163,22 +706,20 @@ async fn authentication_request(
 	// Try and get the login
 	let mut engine = auth_engine.lock().await;
 	// Try and authenticate
-	match engine.verify(body.get_user(), body.get_password().as_bytes()) {
-		None => {
+	engine.verify(body.get_user(), body.get_password().as_bytes())
+		.map_or_else(|| {
 			debug!("Failed authentication");
 			Ok(Response::builder()
 				.status(StatusCode::UNAUTHORIZED)
 				.body("UNAUTHORIZED")
-		)},
-		Some(cookie) => {
+		)}, |cookie| {
 			info!("Successful authentication of user {}", body.get_user());
 			Ok(Response::builder()
 				.status(StatusCode::OK)
-				.header("set-cookie", format!("HEX={}", cookie))
+				.header("set-cookie", format!("HEX={cookie}"))
 				.body("OK")
 			)
-		}
-	}
+		})
 }
 
 #[tracing::instrument(level="debug")]
@@ -187,12 +728,34 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
 		Ok(reply::with_status("NOT_FOUND".into(), StatusCode::NOT_FOUND))
 	} else if err.find::<EmptyRequest>().is_some() {
 		Ok(reply::with_status("Empty Request".into(), StatusCode::BAD_REQUEST))
-	} else if let Some(e) = err.find::<InvalidUrl>() {
-		Ok(reply::with_status(format!("Invalid URL: \"{}\"", e.get_url()), StatusCode::BAD_REQUEST))
+	} else if let Some(e) = err.find::<InvalidUrls>() {
+		let entries: Vec<String> = e.get_entries()
+			.iter()
+			.map(|(url, reason)| format!("\"{url}\": {reason}"))
+			.collect();
+		Ok(reply::with_status(format!("Invalid URLs:\n{}", entries.join("\n")), StatusCode::BAD_REQUEST))
+	} else if let Some(e) = err.find::<TooManyUrls>() {
+		Ok(reply::with_status(format!("Too many URLs: {} (at most {})", e.get_count(), e.get_max()), StatusCode::PAYLOAD_TOO_LARGE))
+	} else if let Some(e) = err.find::<InvalidExpectation>() {
+		Ok(reply::with_status(format!("Invalid expectation for \"{}\": {}", e.get_url(), e.get_reason()), StatusCode::BAD_REQUEST))
+	} else if let Some(e) = err.find::<InvalidOption>() {
+		Ok(reply::with_status(format!("Invalid option: {}", e.get_reason()), StatusCode::BAD_REQUEST))
+	} else if let Some(e) = err.find::<InvalidUpload>() {
+		Ok(reply::with_status(format!("Invalid body: {}", e.get_reason()), StatusCode::BAD_REQUEST))
+	} else if let Some(e) = err.find::<UnsupportedMediaType>() {
+		Ok(reply::with_status(format!("Unsupported media type \"{}\"", e.get_content_type()), StatusCode::UNSUPPORTED_MEDIA_TYPE))
+	} else if let Some(e) = err.find::<InvalidQuery>() {
+		Ok(reply::with_status(format!("Invalid query: {e}"), StatusCode::BAD_REQUEST))
+	} else if err.find::<PayloadTooLarge>().is_some() {
+		Ok(reply::with_status("Payload too large".into(), StatusCode::PAYLOAD_TOO_LARGE))
+	} else if err.find::<LengthRequired>().is_some() {
+		Ok(reply::with_status("Length required".into(), StatusCode::LENGTH_REQUIRED))
 	} else if let Some(e) = err.find::<BodyDeserializeError>() {
-		Ok(reply::with_status(format!("Deserialize error : {}", e), StatusCode::BAD_REQUEST))
+		Ok(reply::with_status(format!("Deserialize error : {e}"), StatusCode::BAD_REQUEST))
 	} else if let Some(e) = err.find::<SyncError<oneshot::error::RecvError>>() {
 		Ok(reply::with_status(format!("Synchronization error : {:?}", e.get_error()), StatusCode::INTERNAL_SERVER_ERROR))
+	} else if let Some(e) = err.find::<Forbidden>() {
+		Ok(reply::with_status(format!("Forbidden: {}", e.get_reason()), StatusCode::FORBIDDEN))
 	} else if err.find::<Unauthorized>().is_some() {
 		Ok(reply::with_status("UNAUTHORIZED".into(), StatusCode::UNAUTHORIZED))
 	} else {
@@ -201,11 +764,12 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
 	}
 }
 
-#[tracing::instrument(level="debug")]
+#[tracing::instrument(level="debug", skip(url_policy))]
 pub async fn start_api(
 	bind_tuple: (&str, u16),
 	manager_req_tx: mpsc::Sender<RequestMessage>,
 	manager_poll_tx: mpsc::Sender<StatusRequestMessage>,
+	url_policy: UrlPolicy,
 	mut shut_rx: broadcast::Receiver<()>
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 	// Authentication engine
@@ -219,6 +783,9 @@ pub async fn start_api(
 	// Turn the queues into filters
 	let manager_req_tx = warp::any().map(move || manager_req_tx.clone());
 	let manager_poll_tx = warp::any().map(move || manager_poll_tx.clone());
+	let max_body = url_policy.get_max_body();
+	let url_policy = Arc::new(url_policy);
+	let url_policy = warp::any().map(move || Arc::clone(&url_policy));
 	debug!("Composing API");
 
 	let healthcheck = warp::path!("healthcheck")
@@ -228,11 +795,23 @@ pub async fn start_api(
 	let new_request = warp::path!("request" / "new")
 		.and(check_authentication(auth_engine.clone()))
 		.untuple_one()
+		.and(check_admin(auth_engine.clone()))
 		.and(manager_req_tx.clone())
-		.and(warp::body::json())
+		.and(url_policy.clone())
+		.and(submission(max_body))
 		.and_then(request_inspection);
 	debug!("Registered /request/new route");
 
+	let new_document = warp::path!("request" / "document")
+		.and(check_authentication(auth_engine.clone()))
+		.untuple_one()
+		.and(check_admin(auth_engine.clone()))
+		.and(manager_req_tx.clone())
+		.and(url_policy.clone())
+		.and(document_submission(max_body))
+		.and_then(document_inspection);
+	debug!("Registered /request/document route");
+
 	let status_request = warp::path("request")
 		.and(check_authentication(auth_engine.clone()))
 		.untuple_one()
@@ -243,6 +822,15 @@ pub async fn start_api(
 		.and_then(request_status);
 	debug!("Registered /request/<uuid>");
 
+	let status_request_v2 = warp::path!("v2" / "request" / ..)
+		.and(check_authentication(auth_engine.clone()))
+		.untuple_one()
+		.and(warp::path::param())
+		.and(warp::path::end())
+		.and(manager_poll_tx.clone())
+		.and_then(request_status_v2);
+	debug!("Registered /v2/request/<uuid>");
+
 	let login = warp::path!("login")
 		.and(warp::filters::method::post())
 		.and(auth_engine.clone())
@@ -252,7 +840,9 @@ pub async fn start_api(
 
 	let routes = healthcheck
 		.or(new_request)
+		.or(new_document)
 		.or(status_request)
+		.or(status_request_v2)
 		.or(login)
 		.recover(handle_rejection);
 
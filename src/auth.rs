@@ -16,12 +16,17 @@ use std::{
 
 pub struct Engine {
 	users: HashMap<String, String>,
-	allowed: Vec<String>
+	admins: Vec<String>,
+	// Cookie to user
+	allowed: HashMap<String, String>
 }
 
 #[derive(Default, Deserialize, Serialize)]
 struct FileFormat {
-	credentials: HashMap<String, String>
+	credentials: HashMap<String, String>,
+	// Users that may use the options that are dangerous for the server
+	#[serde(default)]
+	admins: Vec<String>
 }
 
 impl Engine {
@@ -40,12 +45,18 @@ impl Engine {
 		//users.insert("a_user".into(), argon2::hash_encoded(password.as_bytes(), salt, &config).unwrap());
 		Ok(Self {
 			users: users_file.credentials,
-			allowed: Vec::new()
+			admins: users_file.admins,
+			allowed: HashMap::new()
 		})
 	}
 
 	pub fn is_authorized(&self, cookie: &String) -> bool {
-		self.allowed.contains(cookie)
+		self.allowed.contains_key(cookie)
+	}
+
+	pub fn is_admin(&self, cookie: &String) -> bool {
+		self.allowed.get(cookie)
+			.is_some_and(|user| self.admins.contains(user))
 	}
 
 	pub fn verify(&mut self, user: &str, password: &[u8]) -> Option<String> {
@@ -60,14 +71,14 @@ impl Engine {
 					.take(74)
 					.map(char::from)
 					.collect();
-				self.allowed.push(cookie.clone());
+				self.allowed.insert(cookie.clone(), user.into());
 				Some(cookie)
 			} else {
 				None
 			}
 		} else {
 			// Not exactly secure, but better than nothing
-			std::thread::sleep(std::time::Duration::from_millis(1000));
+			std::thread::sleep(std::time::Duration::from_secs(1));
 			None
 		}
 	}
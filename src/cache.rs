@@ -0,0 +1,85 @@
+//! Results shared between jobs
+//!
+//! Jobs submitted a few minutes apart often have URLs in common. As long
+//! as they are fetched the same way, a recent enough result is as good
+//! as a new one.
+
+use reqwest::Url;
+
+use std::{
+    collections::{
+        hash_map::DefaultHasher,
+        HashMap
+    },
+    hash::{
+        Hash,
+        Hasher
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::{
+    checks::ContentChecks,
+    messages::{
+        CheckMode,
+        FetchOptions,
+        FetchReport
+    }
+};
+
+/// A URL, and a digest of how it is fetched
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    url: Url,
+    fingerprint: u64
+}
+
+impl CacheKey {
+    pub fn new(url: &Url, mode: CheckMode, options: &FetchOptions, checks: Option<&ContentChecks>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        mode.hash(&mut hasher);
+        options.hash(&mut hasher);
+        checks.hash(&mut hasher);
+        Self { url: url.clone(), fingerprint: hasher.finish() }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResultCache {
+    ttl: Duration,
+    entries: HashMap<CacheKey, (Instant, FetchReport)>
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: HashMap::new() }
+    }
+
+    /// A copy of the result stored for `key`, if still fresh
+    pub fn get(&self, key: &CacheKey) -> Option<FetchReport> {
+        self.entries.get(key)
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, report)| {
+                let mut report = report.clone();
+                report.set_cached();
+                report
+            })
+    }
+
+    pub fn insert(&mut self, key: CacheKey, report: &FetchReport) {
+        // Another try could go better
+        if report.get_result().is_transient() {
+            return;
+        }
+        self.entries.insert(key, (Instant::now(), report.clone()));
+    }
+
+    /// Forget everything that is not fresh anymore
+    pub fn purge(&mut self) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, (stored, _)| stored.elapsed() < ttl);
+    }
+}
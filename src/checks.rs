@@ -0,0 +1,142 @@
+//! Assertions on the content of a response
+
+use regex::Regex;
+use serde_json::Value;
+
+use std::hash::{
+    Hash,
+    Hasher
+};
+
+/// Responses bigger than this are not inspected
+pub const MAX_CHECKED_BODY: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Default)]
+pub struct ContentChecks {
+    contains: Vec<String>,
+    not_contains: Vec<String>,
+    regex: Vec<Regex>,
+    json: Vec<(String, Value)>,
+    content_type: Option<String>
+}
+
+// Checks are part of what makes two fetches equivalent
+impl Hash for ContentChecks {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.contains.hash(state);
+        self.not_contains.hash(state);
+        for regex in &self.regex {
+            regex.as_str().hash(state);
+        }
+        for (pointer, value) in &self.json {
+            pointer.hash(state);
+            value.to_string().hash(state);
+        }
+        self.content_type.hash(state);
+    }
+}
+
+impl ContentChecks {
+    pub fn new(
+        contains: Vec<String>,
+        not_contains: Vec<String>,
+        regex: Vec<Regex>,
+        json: Vec<(String, Value)>,
+        content_type: Option<String>
+    ) -> Self {
+        Self { contains, not_contains, regex, json, content_type }
+    }
+
+    fn needs_body(&self) -> bool {
+        !(self.contains.is_empty()
+          && self.not_contains.is_empty()
+          && self.regex.is_empty()
+          && self.json.is_empty())
+    }
+
+    /// Run every check on a response.
+    /// `body` is `None` if it could not be read in full.
+    pub fn evaluate(&self, content_type: Option<&str>, body: Option<&[u8]>) -> CheckOutcome {
+        let mut failures = Vec::new();
+
+        if let Some(expected) = &self.content_type {
+            if !content_type.is_some_and(|ct| mime_matches(expected, ct)) {
+                failures.push(format!(
+                    "content type {} does not match {}",
+                    content_type.unwrap_or("(none)"), expected
+                ));
+            }
+        }
+
+        if !self.needs_body() {
+            return CheckOutcome { failures };
+        }
+        let Some(body) = body else {
+            failures.push(String::from("body could not be read for checking"));
+            return CheckOutcome { failures };
+        };
+
+        let text = String::from_utf8_lossy(body);
+        for needle in &self.contains {
+            if !text.contains(needle.as_str()) {
+                failures.push(format!("body does not contain \"{needle}\""));
+            }
+        }
+        for needle in &self.not_contains {
+            if text.contains(needle.as_str()) {
+                failures.push(format!("body contains \"{needle}\""));
+            }
+        }
+        for re in &self.regex {
+            if !re.is_match(&text) {
+                failures.push(format!("body does not match /{re}/"));
+            }
+        }
+
+        if !self.json.is_empty() {
+            match serde_json::from_slice::<Value>(body) {
+                Err(e) => failures.push(format!("body is not JSON: {e}")),
+                Ok(document) => for (pointer, expected) in &self.json {
+                    match document.pointer(pointer) {
+                        None => failures.push(format!("nothing at JSON pointer {pointer}")),
+                        Some(found) if found != expected => failures.push(format!(
+                            "JSON pointer {pointer} is {found}, not {expected}"
+                        )),
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+
+        CheckOutcome { failures }
+    }
+}
+
+/// Compare the essence of two media types, `type/*` matches any subtype
+fn mime_matches(expected: &str, actual: &str) -> bool {
+    let essence = |mime: &str| mime.split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let (expected, actual) = (essence(expected), essence(actual));
+    expected.strip_suffix("/*").map_or_else(
+        || expected == actual,
+        |kind| actual.split('/').next() == Some(kind)
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckOutcome {
+    failures: Vec<String>
+}
+
+impl CheckOutcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn get_failures(&self) -> &[String] {
+        &self.failures
+    }
+}
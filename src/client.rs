@@ -0,0 +1,522 @@
+//! HTTP client construction
+//!
+//! Requests are built with `reqwest`, which also sends them through
+//! proxies. Requests that go straight to their target are sent over the
+//! connections of our own connector, which times them, see `connector`.
+
+use hyper::http::uri::InvalidUri;
+use openssl::{
+    error::ErrorStack,
+    pkey::{
+        PKey,
+        Private
+    },
+    ssl::{
+        SslConnector,
+        SslMethod
+    },
+    x509::X509
+};
+use reqwest::{
+    header::{
+        HeaderValue,
+        ACCEPT,
+        USER_AGENT
+    },
+    redirect,
+    Certificate,
+    Client,
+    Identity,
+    NoProxy,
+    Proxy,
+    RequestBuilder,
+    Response,
+    Url
+};
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::SocketAddr,
+    sync::Arc
+};
+
+use crate::{
+    connector::TimedConnector,
+    dns::{
+        HostOverrides,
+        HostResolver
+    },
+    egress::{
+        AddressPin,
+        EgressPolicy,
+        PolicyResolver
+    },
+    extras::Secret,
+    messages::FetchOptions
+};
+
+/// Schemes `reqwest` knows how to talk to a proxy with
+pub const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// What we call ourselves, unless told otherwise
+pub const DEFAULT_USER_AGENT: &str = concat!("hexichor/", env!("CARGO_PKG_VERSION"));
+
+/// An outbound proxy, and the hosts that should not go through it
+#[derive(Debug, Clone, Hash)]
+pub struct ProxySettings {
+    url: Secret,
+    no_proxy: Option<String>
+}
+
+impl ProxySettings {
+    pub const fn new(url: Secret, no_proxy: Option<String>) -> Self {
+        Self { url, no_proxy }
+    }
+
+    /// Check that the proxy can be used at all
+    pub fn validate(&self) -> Result<(), String> {
+        let url = self.url()
+            .ok_or_else(|| String::from("invalid proxy URL"))?;
+        if !PROXY_SCHEMES.contains(&url.scheme()) {
+            return Err(format!(
+                "unsupported proxy scheme \"{}\", expected one of {}",
+                url.scheme(), PROXY_SCHEMES.join(", ")
+            ));
+        }
+        self.build().map(|_| ()).map_err(|_| String::from("invalid proxy URL"))
+    }
+
+    fn url(&self) -> Option<Url> {
+        Url::parse(self.url.expose()).ok()
+    }
+
+    fn build(&self) -> reqwest::Result<Proxy> {
+        Ok(Proxy::all(self.url.expose())?
+            .no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string)))
+    }
+}
+
+/// Which proxy a job goes through
+#[derive(Debug, Clone, Default, Hash)]
+pub enum ProxyChoice {
+    /// Whatever the server is configured with
+    #[default]
+    Default,
+    /// No proxy at all, even if the server has one
+    Direct,
+    Custom(ProxySettings)
+}
+
+/// How a job talks TLS to its targets
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+pub struct TlsOptions {
+    insecure: bool,
+    identity: Option<String>
+}
+
+impl TlsOptions {
+    pub const fn new(insecure: bool, identity: Option<String>) -> Self {
+        Self { insecure, identity }
+    }
+
+    /// Whether certificates are accepted without being verified
+    pub const fn is_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    /// Name of the client certificate presented to targets
+    pub fn get_identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+}
+
+/// A client certificate, and its key, for either way of sending requests
+#[derive(Debug, Clone)]
+struct ClientIdentity {
+    identity: Identity,
+    chain: Vec<X509>,
+    key: PKey<Private>
+}
+
+/// Server-wide settings of the HTTP clients
+#[derive(Debug, Clone, Default)]
+pub struct ClientSettings {
+    proxy: Option<ProxySettings>,
+    policy: Arc<EgressPolicy>,
+    resolver: Arc<HostResolver>,
+    roots: Vec<Certificate>,
+    // The same certificates, for the handshakes of the connector
+    tls_roots: Vec<X509>,
+    identities: HashMap<String, ClientIdentity>,
+    user_agent: String
+}
+
+impl ClientSettings {
+    pub fn new(proxy: Option<ProxySettings>, policy: EgressPolicy) -> Self {
+        Self {
+            proxy,
+            policy: Arc::new(policy),
+            user_agent: String::from(DEFAULT_USER_AGENT),
+            ..Self::default()
+        }
+    }
+
+    /// Trust the certificate authorities of the PEM bundle `pem`
+    /// on top of the system ones
+    pub fn add_ca_bundle(&mut self, pem: &[u8]) -> Result<(), String> {
+        let certs = X509::stack_from_pem(pem)
+            .map_err(|e| format!("invalid CA bundle: {e}"))?;
+        if certs.is_empty() {
+            return Err(String::from("invalid CA bundle: no certificate found"));
+        }
+        self.roots.extend(Certificate::from_pem_bundle(pem)
+            .map_err(|e| format!("invalid CA bundle: {e}"))?);
+        self.tls_roots.extend(certs);
+        Ok(())
+    }
+
+    /// Register a client certificate, and its key, that jobs
+    /// can present to targets under `name`
+    pub fn add_identity(&mut self, name: String, cert: &[u8], key: &[u8]) -> Result<(), String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid client identity \"{name}\": {e}");
+        let identity = Identity::from_pkcs8_pem(cert, key)
+            .map_err(|e| invalid(&e))?;
+        let chain = X509::stack_from_pem(cert)
+            .map_err(|e| invalid(&e))?;
+        let key = PKey::private_key_from_pem(key)
+            .map_err(|e| invalid(&e))?;
+        self.identities.insert(name, ClientIdentity { identity, chain, key });
+        Ok(())
+    }
+
+    /// Ask these DNS servers instead of the system resolver
+    pub fn set_dns_servers(&mut self, servers: Vec<SocketAddr>) {
+        self.resolver = Arc::new(HostResolver::new(servers));
+    }
+
+    /// Sent with every request, and looked for in robots.txt
+    pub fn set_user_agent(&mut self, user_agent: String) {
+        self.user_agent = user_agent;
+    }
+
+    pub fn get_user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    /// The resolver of a job that pins some hosts to `overrides`
+    pub fn resolver_for(&self, overrides: &HostOverrides) -> Arc<HostResolver> {
+        if overrides.is_empty() {
+            Arc::clone(&self.resolver)
+        } else {
+            Arc::new(self.resolver.with_overrides(overrides))
+        }
+    }
+
+    pub fn get_policy(&self) -> &Arc<EgressPolicy> {
+        &self.policy
+    }
+
+    /// Make sure a proxy picked by a user is not a way around the policy.
+    /// The proxy of the server is trusted.
+    pub async fn check_proxy(&self, choice: &ProxyChoice) -> Result<(), String> {
+        let ProxyChoice::Custom(proxy) = choice else {
+            return Ok(());
+        };
+        let url = proxy.url()
+            .ok_or_else(|| String::from("invalid proxy URL"))?;
+        self.policy.check_url(&url)
+            .map_err(|e| format!("proxy {e}"))?;
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return Err(String::from("invalid proxy URL"));
+        };
+        self.policy.resolve(&self.resolver, host.trim_start_matches('[').trim_end_matches(']'), port).await
+            .map(|_| ())
+            .map_err(|e| format!("proxy {e}"))
+    }
+
+    /// Make sure the client certificate a job asks for exists
+    pub fn check_tls(&self, tls: &TlsOptions) -> Result<(), String> {
+        match tls.get_identity() {
+            Some(name) if !self.identities.contains_key(name) => {
+                Err(format!("unknown client identity \"{name}\""))
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// The proxy a job ends up using
+    pub fn effective_proxy<'a>(&'a self, choice: &'a ProxyChoice) -> Option<&'a ProxySettings> {
+        match choice {
+            ProxyChoice::Default => self.proxy.as_ref(),
+            ProxyChoice::Direct => None,
+            ProxyChoice::Custom(proxy) => Some(proxy)
+        }
+    }
+
+    pub fn build(&self, options: &FetchOptions) -> Result<HttpClient, BuildError> {
+        self.build_pinned(options, AddressPin::Any)
+    }
+
+    /// A client that only connects to the addresses `pin` allows.
+    /// Behind the proxy of the server, pins and host overrides have no effect.
+    pub fn build_pinned(&self, options: &FetchOptions, pin: AddressPin) -> Result<HttpClient, BuildError> {
+        let (choice, tls) = (options.get_proxy(), options.get_tls());
+        let identity = tls.get_identity().and_then(|name| self.identities.get(name));
+        // Redirections are followed by the workers themselves
+        // so that every hop can be reported
+        let mut builder = Client::builder()
+            .user_agent(&self.user_agent)
+            .redirect(redirect::Policy::none())
+            .danger_accept_invalid_certs(tls.is_insecure());
+        for root in &self.roots {
+            builder = builder.add_root_certificate(root.clone());
+        }
+        if let Some(identity) = identity {
+            builder = builder.identity(identity.identity.clone());
+        }
+        // Proxies of the environment would go around the resolver, and
+        // so around the egress policy: only the ones we are told of count
+        let builder = match self.effective_proxy(choice) {
+            Some(proxy) => builder.no_proxy().proxy(proxy.build()?),
+            None => builder.no_proxy()
+        };
+        let resolver = PolicyResolver::new(
+            Arc::clone(&self.policy),
+            self.resolver_for(options.get_overrides()),
+            pin
+        );
+        // Behind the proxy of the server, we only ever resolve
+        // the proxy itself and the targets are its business
+        let builder = match (choice, &self.proxy) {
+            (ProxyChoice::Default, Some(_)) => builder,
+            _ => builder.dns_resolver(Arc::new(resolver.clone()))
+        };
+        let client = builder.build()?;
+        if self.effective_proxy(choice).is_some() {
+            return Ok(HttpClient { client, direct: None, user_agent: self.user_agent_header() });
+        }
+
+        let mut tls_builder = SslConnector::builder(SslMethod::tls_client())?;
+        for root in &self.tls_roots {
+            tls_builder.cert_store_mut().add_cert(root.clone())?;
+        }
+        if let Some(identity) = identity {
+            let mut chain = identity.chain.iter();
+            if let Some(leaf) = chain.next() {
+                tls_builder.set_certificate(leaf)?;
+            }
+            for cert in chain {
+                tls_builder.add_extra_chain_cert(cert.clone())?;
+            }
+            tls_builder.set_private_key(&identity.key)?;
+        }
+        let connector = TimedConnector::new(resolver, tls_builder.build(), tls.is_insecure());
+        Ok(HttpClient {
+            client,
+            direct: Some(hyper::Client::builder().build(connector)),
+            user_agent: self.user_agent_header()
+        })
+    }
+
+    fn user_agent_header(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.user_agent)
+            .unwrap_or_else(|_| HeaderValue::from_static(DEFAULT_USER_AGENT))
+    }
+}
+
+/// Why a client could not be built
+#[derive(Debug)]
+pub enum BuildError {
+    Client(reqwest::Error),
+    Tls(ErrorStack)
+}
+
+impl From<reqwest::Error> for BuildError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Client(err)
+    }
+}
+
+impl From<ErrorStack> for BuildError {
+    fn from(err: ErrorStack) -> Self {
+        Self::Tls(err)
+    }
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Client(err) => err.fmt(f),
+            Self::Tls(err) => write!(f, "TLS setup failed: {err}")
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/// Why a request got no response
+#[derive(Debug)]
+pub enum SendError {
+    /// From `reqwest`, when the request is invalid or goes through a proxy
+    Request(reqwest::Error),
+    /// From our own connections
+    Connection(hyper::Error),
+    /// The URL cannot be written in a request
+    Uri(InvalidUri)
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(err) => err.fmt(f),
+            Self::Connection(err) => err.fmt(f),
+            Self::Uri(err) => write!(f, "invalid URL: {err}")
+        }
+    }
+}
+
+impl Error for SendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Request(err) => Some(err),
+            Self::Connection(err) => Some(err),
+            Self::Uri(err) => Some(err)
+        }
+    }
+}
+
+/// The client of a job
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    // Builds every request, and sends those that go through a proxy
+    client: Client,
+    // Sends the others, over connections that it times
+    direct: Option<hyper::Client<TimedConnector>>,
+    user_agent: HeaderValue
+}
+
+impl HttpClient {
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, SendError> {
+        let Some(direct) = &self.direct else {
+            return request.send().await.map_err(SendError::Request);
+        };
+        let request = request.build().map_err(SendError::Request)?;
+        let mut headers = request.headers().clone();
+        // What `reqwest` would have added
+        headers.entry(USER_AGENT).or_insert_with(|| self.user_agent.clone());
+        headers.entry(ACCEPT).or_insert_with(|| HeaderValue::from_static("*/*"));
+        let body = request.body()
+            .and_then(reqwest::Body::as_bytes)
+            .map_or_else(hyper::Body::empty, |bytes| hyper::Body::from(bytes.to_vec()));
+        let mut outgoing = hyper::Request::new(body);
+        *outgoing.method_mut() = request.method().clone();
+        *outgoing.uri_mut() = request.url().as_str().parse()
+            .map_err(SendError::Uri)?;
+        *outgoing.headers_mut() = headers;
+        direct.request(outgoing).await
+            .map(Response::from)
+            .map_err(SendError::Connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::{
+        io::{
+            AsyncReadExt,
+            AsyncWriteExt
+        },
+        net::TcpListener,
+        process::Command
+    };
+
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering
+    };
+
+    use crate::extras::HostScope;
+
+    /// A server answering `name` to every request, counting them
+    async fn stand_in(name: &'static str) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buffer = [0u8; 4096];
+                let _ = stream.read(&mut buffer).await;
+                let reply = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{name}", name.len());
+                let _ = stream.write_all(reply.as_bytes()).await;
+            }
+        });
+        (addr, hits)
+    }
+
+    fn settings(proxy: Option<SocketAddr>) -> ClientSettings {
+        let proxy = proxy.map(|addr| ProxySettings::new(format!("http://{addr}").into(), None));
+        ClientSettings::new(proxy, EgressPolicy::new(false, Vec::new(), Vec::new(), HostScope::default(), HostScope::default()))
+    }
+
+    /// What `client` gets for a host pinned to the target
+    async fn fetch(settings: &ClientSettings, choice: ProxyChoice, target: SocketAddr) -> String {
+        let options = FetchOptions::default()
+            .with_proxy(choice)
+            .with_overrides(HashMap::from([(String::from("target.test"), vec![target.ip()])]));
+        let client = settings.build(&options).unwrap();
+        let url = Url::parse(&format!("http://target.test:{}/", target.port())).unwrap();
+        client.send(client.get(url)).await.unwrap()
+            .text().await.unwrap()
+    }
+
+    /// Set, with the address of a target, on the copy of the tests
+    /// spawned to fetch it with proxies in its environment
+    const TARGET_VARIABLE: &str = "HEXICHOR_TEST_PROXIED_TARGET";
+
+    /// Environment variables are global to the process, and tests run in
+    /// parallel, so the fetch happens in a copy of the tests of its own
+    #[tokio::test]
+    async fn proxies_of_the_environment_are_ignored() {
+        let (proxy, proxy_hits) = stand_in("proxy").await;
+        let (target, target_hits) = stand_in("target").await;
+        let mut child = Command::new(std::env::current_exe().unwrap());
+        child.args(["--exact", "client::tests::fetch_with_proxies_in_the_environment", "--ignored", "--test-threads=1"])
+            .env(TARGET_VARIABLE, target.to_string())
+            .env_remove("NO_PROXY")
+            .env_remove("no_proxy");
+        for name in ["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"] {
+            child.env(name, format!("http://{proxy}"));
+        }
+        let output = child.output().await.unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+        assert_eq!(target_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(proxy_hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "spawned by proxies_of_the_environment_are_ignored"]
+    async fn fetch_with_proxies_in_the_environment() {
+        let Ok(target) = std::env::var(TARGET_VARIABLE) else {
+            return;
+        };
+        assert_eq!(fetch(&settings(None), ProxyChoice::Default, target.parse().unwrap()).await, "target");
+    }
+
+    #[tokio::test]
+    async fn the_proxy_of_the_server_is_used() {
+        let (proxy, _) = stand_in("proxy").await;
+        let (target, _) = stand_in("target").await;
+        let settings = settings(Some(proxy));
+        assert_eq!(fetch(&settings, ProxyChoice::Default, target).await, "proxy");
+        assert_eq!(fetch(&settings, ProxyChoice::Direct, target).await, "target");
+    }
+}
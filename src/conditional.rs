@@ -0,0 +1,45 @@
+//! Conditional requests
+//!
+//! Servers tag what they send with an `ETag` or a `Last-Modified` date.
+//! Handing those back on the next fetch lets them answer `304 Not Modified`
+//! instead of sending the whole page again.
+
+use reqwest::{
+    header::{
+        HeaderMap,
+        ETAG,
+        IF_MODIFIED_SINCE,
+        IF_NONE_MATCH,
+        LAST_MODIFIED
+    },
+    RequestBuilder
+};
+
+#[derive(Debug, Clone)]
+pub struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>
+}
+
+impl Validators {
+    /// The validators of a response, if it has any
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let get = |name| headers.get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(String::from);
+        let etag = get(ETAG);
+        let last_modified = get(LAST_MODIFIED);
+        (etag.is_some() || last_modified.is_some())
+            .then_some(Self { etag, last_modified })
+    }
+
+    pub fn apply(&self, mut builder: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            builder = builder.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            builder = builder.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        builder
+    }
+}
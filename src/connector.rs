@@ -0,0 +1,527 @@
+//! Timed connections
+//!
+//! `reqwest` does not tell how long each phase of a connection took, so
+//! fetches that go straight to their target are sent over connections of
+//! our own: the connector resolves the host, connects to it and runs the
+//! TLS handshake, timing every step. What it learned comes with every
+//! response sent over the connection, along with the certificates the
+//! server presented, even when they could not be verified.
+
+use hyper::{
+    client::connect::{
+        Connected,
+        Connection
+    },
+    service::Service,
+    Uri
+};
+use openssl::ssl::{
+    SslConnector,
+    SslVerifyMode
+};
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        ReadBuf
+    },
+    net::TcpStream,
+    time::timeout
+};
+use tokio_openssl::SslStream;
+
+use std::{
+    error::Error,
+    future::Future,
+    io,
+    net::{
+        IpAddr,
+        SocketAddr
+    },
+    pin::Pin,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering
+        },
+        Arc
+    },
+    task::{
+        Context,
+        Poll
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::{
+    egress::PolicyResolver,
+    tls::TlsReport
+};
+
+/// How long every address, and then the TLS handshake, gets, so that
+/// an address that never answers does not hold up the others for long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+fn timed_out(phase: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("{phase} timed out"))
+}
+
+/// What a fetch learns from the connection it went through
+#[derive(Debug, Clone)]
+pub struct ConnectInfo {
+    // No resolution for addresses written in URLs
+    dns: Option<Duration>,
+    connect: Duration,
+    tls: Option<Duration>,
+    // When the connection was ready to carry a request
+    ready: Instant,
+    addresses: Vec<IpAddr>,
+    address: IpAddr,
+    certificates: Option<TlsReport>,
+    // Until a response claims the phases of the connection
+    fresh: Arc<AtomicBool>
+}
+
+impl ConnectInfo {
+    /// Whether the phases are those of the response at hand: only the
+    /// first response over a connection went through them, the ones
+    /// after it reused the connection
+    pub fn claim(&self) -> bool {
+        self.fresh.swap(false, Ordering::SeqCst)
+    }
+
+    pub const fn get_dns(&self) -> Option<Duration> {
+        self.dns
+    }
+
+    pub const fn get_connect(&self) -> Duration {
+        self.connect
+    }
+
+    pub const fn get_tls(&self) -> Option<Duration> {
+        self.tls
+    }
+
+    pub const fn get_ready(&self) -> Instant {
+        self.ready
+    }
+
+    /// Where the host resolved to
+    pub fn get_addresses(&self) -> &[IpAddr] {
+        &self.addresses
+    }
+
+    /// The address that was connected to
+    pub const fn get_address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub const fn get_certificates(&self) -> Option<&TlsReport> {
+        self.certificates.as_ref()
+    }
+}
+
+/// The certificates of the server could not be verified
+#[derive(Debug)]
+pub struct CertificateRejected(Box<TlsReport>);
+
+impl CertificateRejected {
+    pub fn get_report(&self) -> &TlsReport {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for CertificateRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "certificate verify failed: {}", self.0.get_verify_error().unwrap_or("unknown error"))
+    }
+}
+
+impl Error for CertificateRejected {}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(SslStream<TcpStream>)
+}
+
+/// A connection made by the connector, and what it learned on the way
+pub struct TimedStream {
+    stream: Stream,
+    info: ConnectInfo
+}
+
+impl AsyncRead for TimedStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf)
+        }
+    }
+}
+
+impl AsyncWrite for TimedStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx)
+        }
+    }
+}
+
+impl Connection for TimedStream {
+    fn connected(&self) -> Connected {
+        // Handed to every response sent over the connection
+        Connected::new().extra(self.info.clone())
+    }
+}
+
+/// Connect to the first of `addrs` that accepts
+async fn connect(addrs: &[SocketAddr]) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "No address for host");
+    for addr in addrs {
+        match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok((stream, *addr)),
+            Ok(Err(e)) => last = e,
+            Err(_) => last = timed_out(&format!("connection to {addr}"))
+        }
+    }
+    Err(last)
+}
+
+/// The connector of the HTTP clients that do not go through a proxy
+#[derive(Clone)]
+pub struct TimedConnector {
+    resolver: Arc<PolicyResolver>,
+    tls: SslConnector,
+    // Certificates are still looked at, but not verified
+    insecure: bool
+}
+
+impl std::fmt::Debug for TimedConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimedConnector")
+            .field("resolver", &self.resolver)
+            .field("insecure", &self.insecure)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TimedConnector {
+    pub fn new(resolver: PolicyResolver, tls: SslConnector, insecure: bool) -> Self {
+        Self { resolver: Arc::new(resolver), tls, insecure }
+    }
+
+    async fn handshake(&self, host: &str, stream: TcpStream, address: IpAddr) -> Result<(SslStream<TcpStream>, Option<TlsReport>), BoxError> {
+        let mut config = self.tls.configure()?;
+        if self.insecure {
+            config.set_verify(SslVerifyMode::NONE);
+        }
+        let ssl = config.verify_hostname(!self.insecure).into_ssl(host)?;
+        let mut stream = SslStream::new(ssl, stream)?;
+        let handshake = timeout(CONNECT_TIMEOUT, Pin::new(&mut stream).connect()).await;
+        // The chain is still there when it could not be verified
+        let certificates = TlsReport::inspect(stream.ssl(), host, address);
+        match (handshake, certificates) {
+            (Ok(Ok(())), certificates) => Ok((stream, certificates)),
+            (Ok(Err(_)), Some(report)) if report.get_verify_error().is_some() => {
+                Err(Box::new(CertificateRejected(Box::new(report))))
+            },
+            (Ok(Err(e)), _) => Err(Box::new(e)),
+            (Err(_), _) => Err(Box::new(timed_out("TLS handshake")))
+        }
+    }
+
+    async fn open(self, uri: Uri) -> Result<TimedStream, BoxError> {
+        let host = uri.host()
+            .ok_or("no host in URL")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let https = uri.scheme_str() == Some("https");
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let start = Instant::now();
+        let (addrs, dns) = match host.parse::<IpAddr>() {
+            Ok(ip) => (vec![SocketAddr::new(ip, port)], None),
+            Err(_) => (self.resolver.lookup(host, port).await?, Some(start.elapsed()))
+        };
+
+        let start = Instant::now();
+        let (tcp, addr) = connect(&addrs).await?;
+        tcp.set_nodelay(true)?;
+        let connect = start.elapsed();
+
+        let (stream, tls, certificates) = if https {
+            let start = Instant::now();
+            let (stream, certificates) = self.handshake(host, tcp, addr.ip()).await?;
+            (Stream::Tls(stream), Some(start.elapsed()), certificates)
+        } else {
+            (Stream::Plain(tcp), None, None)
+        };
+
+        let info = ConnectInfo {
+            dns,
+            connect,
+            tls,
+            ready: Instant::now(),
+            addresses: addrs.iter().map(SocketAddr::ip).collect(),
+            address: addr.ip(),
+            certificates,
+            fresh: Arc::new(AtomicBool::new(true))
+        };
+        Ok(TimedStream { stream, info })
+    }
+}
+
+impl Service<Uri> for TimedConnector {
+    type Response = TimedStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<TimedStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        Box::pin(self.clone().open(uri))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use openssl::{
+        asn1::Asn1Time,
+        bn::{
+            BigNum,
+            MsbOption
+        },
+        ec::{
+            EcGroup,
+            EcKey
+        },
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{
+            PKey,
+            Private
+        },
+        ssl::{
+            Ssl,
+            SslAcceptor,
+            SslMethod
+        },
+        x509::{
+            extension::SubjectAlternativeName,
+            X509NameBuilder,
+            X509
+        }
+    };
+    use reqwest::Url;
+    use tokio::{
+        io::{
+            AsyncReadExt,
+            AsyncWriteExt
+        },
+        net::TcpListener
+    };
+
+    use std::collections::HashMap;
+
+    use crate::{
+        client::{
+            ClientSettings,
+            HttpClient,
+            TlsOptions
+        },
+        egress::EgressPolicy,
+        extras::HostScope,
+        messages::FetchOptions
+    };
+
+    /// A certificate for `localhost`, signed by its own key
+    fn self_signed(days: u32) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "localhost").unwrap();
+        let name = name.build();
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(days).unwrap()).unwrap();
+        let sans = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(sans).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    /// A TLS server presenting `cert`, answering every request
+    /// with a short page and keeping its connections open
+    async fn serve(cert: X509, key: PKey<Private>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        let acceptor = acceptor.build();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let ssl = Ssl::new(acceptor.context()).unwrap();
+                tokio::spawn(async move {
+                    let mut stream = SslStream::new(ssl, stream).unwrap();
+                    if Pin::new(&mut stream).accept().await.is_err() {
+                        return;
+                    }
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 4096];
+                    while let Ok(read @ 1..) = stream.read(&mut buffer).await {
+                        request.extend_from_slice(&buffer[..read]);
+                        if request.windows(4).any(|window| window == b"\r\n\r\n") {
+                            request.clear();
+                            let reply = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+                            if stream.write_all(reply).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// A client for `localhost`, trusting `cert` if given
+    fn client(trusted: Option<&X509>, insecure: bool) -> HttpClient {
+        let policy = EgressPolicy::new(false, Vec::new(), Vec::new(), HostScope::default(), HostScope::default());
+        let mut settings = ClientSettings::new(None, policy);
+        if let Some(cert) = trusted {
+            settings.add_ca_bundle(&cert.to_pem().unwrap()).unwrap();
+        }
+        let options = FetchOptions::default()
+            .with_tls(TlsOptions::new(insecure, None))
+            .with_overrides(HashMap::from([(String::from("localhost"), vec![IpAddr::from([127, 0, 0, 1])])]));
+        settings.build(&options).unwrap()
+    }
+
+    /// What the connection to `host` found out, or why it failed
+    async fn get(client: &HttpClient, host: &str, addr: SocketAddr) -> Result<ConnectInfo, Box<dyn Error>> {
+        let url = Url::parse(&format!("https://{host}:{}/", addr.port())).unwrap();
+        let response = client.send(client.get(url)).await?;
+        let info = response.extensions().get::<ConnectInfo>().cloned().unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        Ok(info)
+    }
+
+    /// The certificates that made a fetch fail
+    fn rejected(err: &(dyn Error + 'static)) -> TlsReport {
+        let mut source = Some(err);
+        while let Some(inner) = source {
+            if let Some(rejected) = inner.downcast_ref::<CertificateRejected>() {
+                return rejected.get_report().clone();
+            }
+            source = inner.source();
+        }
+        panic!("not a certificate failure: {err}");
+    }
+
+    #[tokio::test]
+    async fn connections_are_timed_and_inspected() {
+        let (cert, key) = self_signed(30);
+        let addr = serve(cert.clone(), key).await;
+        let client = client(Some(&cert), false);
+        let info = get(&client, "localhost", addr).await.unwrap();
+        assert!(info.get_dns().is_some());
+        assert!(info.get_tls().is_some());
+        assert_eq!(info.get_address(), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(info.get_addresses(), [IpAddr::from([127, 0, 0, 1])]);
+        let report = info.get_certificates().unwrap();
+        assert_eq!(report.get_address(), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(report.get_verify_error(), None);
+        assert!(report.has_hostname_match());
+        assert_eq!(report.get_chain()[0].get_subject(), "CN=localhost");
+        assert_eq!(report.get_chain()[0].get_sans(), ["localhost"]);
+        assert!((28..=30).contains(&report.days_left().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn only_the_first_response_claims_the_phases() {
+        let (cert, key) = self_signed(30);
+        let addr = serve(cert.clone(), key).await;
+        let client = client(Some(&cert), false);
+        let first = get(&client, "localhost", addr).await.unwrap();
+        assert!(first.claim());
+        let second = get(&client, "localhost", addr).await.unwrap();
+        assert_eq!(second.get_ready(), first.get_ready());
+        assert!(!second.claim());
+    }
+
+    #[tokio::test]
+    async fn untrusted_certificates_are_reported_and_refused() {
+        let (cert, key) = self_signed(30);
+        let addr = serve(cert, key).await;
+        let err = get(&client(None, false), "localhost", addr).await.unwrap_err();
+        let report = rejected(err.as_ref());
+        assert!(report.get_verify_error().is_some());
+        assert_eq!(report.get_chain().len(), 1);
+        assert_eq!(report.get_chain()[0].get_subject(), "CN=localhost");
+    }
+
+    #[tokio::test]
+    async fn other_hosts_do_not_match() {
+        let (cert, key) = self_signed(30);
+        let addr = serve(cert.clone(), key).await;
+        let err = get(&client(Some(&cert), false), "127.0.0.1", addr).await.unwrap_err();
+        let report = rejected(err.as_ref());
+        assert!(!report.has_hostname_match());
+        assert!(report.get_verify_error().is_some());
+    }
+
+    #[tokio::test]
+    async fn insecure_jobs_still_look_at_certificates() {
+        let (cert, key) = self_signed(30);
+        let addr = serve(cert, key).await;
+        let info = get(&client(None, true), "localhost", addr).await.unwrap();
+        assert!(info.get_certificates().unwrap().get_verify_error().is_some());
+    }
+
+    #[tokio::test]
+    async fn expiring_certificates_are_flagged() {
+        let (cert, key) = self_signed(3);
+        let addr = serve(cert.clone(), key).await;
+        let info = get(&client(Some(&cert), false), "localhost", addr).await.unwrap();
+        let mut report = info.get_certificates().unwrap().clone();
+        report.set_warning(7);
+        assert!(report.is_expiring());
+        report.set_warning(1);
+        assert!(!report.is_expiring());
+    }
+}
@@ -0,0 +1,552 @@
+//! Host resolution
+//!
+//! Names are resolved by the system by default, like for any other
+//! program on the server. The server can instead be pointed at DNS
+//! servers of its own, which are then asked directly over UDP, or TCP for
+//! answers too big for a datagram, and a job can pin names to addresses of
+//! its choosing without either knowing.
+
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt
+    },
+    net::{
+        TcpStream,
+        UdpSocket
+    },
+    task::spawn_blocking,
+    time::timeout
+};
+
+use std::{
+    collections::HashMap,
+    ffi::{
+        CStr,
+        CString
+    },
+    io,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+        SocketAddrV6
+    },
+    ptr,
+    time::Duration
+};
+
+/// How long a DNS server gets to answer a query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+
+/// Why a DNS server did not give us addresses
+#[derive(Debug)]
+pub enum DnsError {
+    NotFound(String),
+    /// The name exists, but has no address
+    NoData(String),
+    Timeout(String),
+    Failure(String)
+}
+
+impl std::fmt::Display for DnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(host) => write!(f, "{host} does not exist"),
+            Self::NoData(host) => write!(f, "{host} has no address"),
+            Self::Timeout(host) => write!(f, "no DNS server answered for {host}"),
+            Self::Failure(reason) => write!(f, "DNS failure: {reason}")
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+/// Host names pinned to addresses, like curl's `--resolve`
+pub type HostOverrides = HashMap<String, Vec<IpAddr>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct HostResolver {
+    servers: Vec<SocketAddr>,
+    overrides: HostOverrides
+}
+
+impl HostResolver {
+    /// Ask `servers` in turn, or the system if there are none
+    pub fn new(servers: Vec<SocketAddr>) -> Self {
+        Self { servers, overrides: HashMap::new() }
+    }
+
+    /// The same resolver, answering for `overrides` on its own
+    pub fn with_overrides(&self, overrides: &HostOverrides) -> Self {
+        Self {
+            servers: self.servers.clone(),
+            overrides: overrides.clone()
+        }
+    }
+
+    /// Whether the job pinned `host` to addresses of its own
+    pub fn overrides(&self, host: &str) -> bool {
+        self.overrides.contains_key(&host.trim_end_matches('.').to_ascii_lowercase())
+    }
+
+    pub async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let to_addrs = |ips: &[IpAddr]| ips.iter()
+            .map(|ip| SocketAddr::new(*ip, port))
+            .collect();
+        if let Some(ips) = self.overrides.get(&host) {
+            return Ok(to_addrs(ips));
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        if self.servers.is_empty() {
+            return spawn_blocking(move || getaddrinfo(&host, port)).await?;
+        }
+        let mut last = DnsError::Timeout(host.clone());
+        for server in &self.servers {
+            match query_server(*server, &host).await {
+                Ok(ips) => return Ok(to_addrs(&ips)),
+                // The next server would not know better
+                Err(e @ (DnsError::NotFound(_) | DnsError::NoData(_))) => return Err(io::Error::other(e)),
+                Err(e) => last = e
+            }
+        }
+        Err(io::Error::other(last))
+    }
+}
+
+/// The addresses of `host` according to the system
+///
+/// The standard library only keeps the message of `getaddrinfo` when it
+/// fails, so it is called directly to tell missing names from resolvers
+/// that could not be reached.
+fn getaddrinfo(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    let node = CString::new(host)
+        .map_err(|_| io::Error::other(DnsError::Failure(format!("invalid host name {host}"))))?;
+    // SAFETY: an address information full of zeroes asks for anything
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut first = ptr::null_mut();
+    // SAFETY: both strings outlive the call, and `first` is only read
+    // when it succeeded
+    let code = unsafe { libc::getaddrinfo(node.as_ptr(), ptr::null(), &raw const hints, &raw mut first) };
+    match code {
+        0 => {},
+        libc::EAI_NONAME => return Err(io::Error::other(DnsError::NotFound(host.into()))),
+        libc::EAI_NODATA => return Err(io::Error::other(DnsError::NoData(host.into()))),
+        libc::EAI_AGAIN => return Err(io::Error::other(DnsError::Timeout(host.into()))),
+        libc::EAI_SYSTEM => return Err(io::Error::last_os_error()),
+        code => {
+            // SAFETY: the message of any code is a static string
+            let reason = unsafe { CStr::from_ptr(libc::gai_strerror(code)) };
+            return Err(io::Error::other(DnsError::Failure(reason.to_string_lossy().into_owned())));
+        }
+    }
+    let mut addrs = Vec::new();
+    let mut cursor = first;
+    while !cursor.is_null() {
+        // SAFETY: the list stays alive until it is freed below, and each
+        // address has the layout of its family
+        let info = unsafe { &*cursor };
+        match info.ai_family {
+            libc::AF_INET => {
+                let addr = unsafe { info.ai_addr.cast::<libc::sockaddr_in>().read_unaligned() };
+                addrs.push(SocketAddr::new(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into(), port));
+            },
+            libc::AF_INET6 => {
+                let addr = unsafe { info.ai_addr.cast::<libc::sockaddr_in6>().read_unaligned() };
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                addrs.push(SocketAddrV6::new(ip, port, addr.sin6_flowinfo, addr.sin6_scope_id).into());
+            },
+            _ => {}
+        }
+        cursor = info.ai_next;
+    }
+    // SAFETY: the list came from `getaddrinfo` and is not used anymore
+    unsafe { libc::freeaddrinfo(first) };
+    Ok(addrs)
+}
+
+/// The A and AAAA records of `host` according to `server`
+///
+/// Either kind of record is enough : a server can fail to answer one of
+/// them, and hosts often only have addresses of one family.
+async fn query_server(server: SocketAddr, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+    let local: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await
+        .map_err(|e| DnsError::Failure(e.to_string()))?;
+    socket.connect(server).await
+        .map_err(|e| DnsError::Failure(e.to_string()))?;
+    let v4 = query(&socket, server, host, TYPE_A).await;
+    let v6 = query(&socket, server, host, TYPE_AAAA).await;
+    merge_answers(host, v4, v6)
+}
+
+/// The addresses of both answers, or why there are none
+fn merge_answers(
+    host: &str,
+    v4: Result<Vec<IpAddr>, DnsError>,
+    v6: Result<Vec<IpAddr>, DnsError>
+) -> Result<Vec<IpAddr>, DnsError> {
+    match (v4, v6) {
+        (Ok(mut v4), Ok(v6)) => {
+            v4.extend(v6);
+            if v4.is_empty() {
+                Err(DnsError::NoData(host.into()))
+            } else {
+                Ok(v4)
+            }
+        },
+        (Ok(ips), Err(e)) | (Err(e), Ok(ips)) => if ips.is_empty() {
+            Err(e)
+        } else {
+            Ok(ips)
+        },
+        // The name not existing is the answer that matters
+        (Err(e @ DnsError::NotFound(_)), Err(_)) | (Err(_), Err(e)) => Err(e)
+    }
+}
+
+async fn query(socket: &UdpSocket, server: SocketAddr, host: &str, qtype: u16) -> Result<Vec<IpAddr>, DnsError> {
+    let id: u16 = rand::random();
+    let packet = build_query(id, host, qtype)
+        .ok_or_else(|| DnsError::Failure(format!("invalid host name {host}")))?;
+    socket.send(&packet).await
+        .map_err(|e| DnsError::Failure(e.to_string()))?;
+    let mut buffer = [0u8; 1500];
+    let answer = timeout(QUERY_TIMEOUT, async {
+        loop {
+            let len = socket.recv(&mut buffer).await?;
+            // Late answers to an earlier query, or answers to
+            // somebody else's, are not ours
+            if answers(&buffer[..len], id, host, qtype) {
+                return Ok::<usize, io::Error>(len);
+            }
+        }
+    }).await;
+    match answer {
+        Ok(Ok(len)) if flags(&buffer[..len]) & FLAG_TRUNCATED != 0 => query_tcp(server, host, qtype).await,
+        Ok(Ok(len)) => parse_answer(&buffer[..len], host, qtype),
+        Ok(Err(e)) => Err(DnsError::Failure(e.to_string())),
+        Err(_) => Err(DnsError::Timeout(host.into()))
+    }
+}
+
+/// Ask `server` again over TCP, for answers that did not fit in a datagram
+async fn query_tcp(server: SocketAddr, host: &str, qtype: u16) -> Result<Vec<IpAddr>, DnsError> {
+    let id: u16 = rand::random();
+    let packet = build_query(id, host, qtype)
+        .ok_or_else(|| DnsError::Failure(format!("invalid host name {host}")))?;
+    let answer = timeout(QUERY_TIMEOUT, async {
+        let mut stream = TcpStream::connect(server).await?;
+        // Messages are preceded by their length
+        let len = u16::try_from(packet.len()).map_err(io::Error::other)?;
+        stream.write_all(&[&len.to_be_bytes()[..], &packet].concat()).await?;
+        let len = stream.read_u16().await?;
+        let mut answer = vec![0; usize::from(len)];
+        stream.read_exact(&mut answer).await?;
+        Ok::<Vec<u8>, io::Error>(answer)
+    }).await;
+    match answer {
+        Ok(Ok(answer)) if answers(&answer, id, host, qtype) => parse_answer(&answer, host, qtype),
+        Ok(Ok(_)) => Err(DnsError::Failure(String::from("answer does not match the query"))),
+        Ok(Err(e)) => Err(DnsError::Failure(e.to_string())),
+        Err(_) => Err(DnsError::Timeout(host.into()))
+    }
+}
+
+fn build_query(id: u16, host: &str, qtype: u16) -> Option<Vec<u8>> {
+    let mut packet = Vec::with_capacity(32 + host.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.split('.') {
+        let len = u8::try_from(label.len()).ok().filter(|len| (1..64).contains(len))?;
+        packet.push(len);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Some(packet)
+}
+
+/// The name starting at `pos`, in lower case, and where it ends
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Compression could loop, but not for longer than the packet
+    for _ in 0..packet.len() {
+        let len = *packet.get(pos)?;
+        match len {
+            0 => return Some((labels.join("."), end.unwrap_or(pos + 1))),
+            len if len & 0xc0 == 0xc0 => {
+                end.get_or_insert(pos + 2);
+                pos = usize::from(read_u16(packet, pos)? & 0x3fff);
+            },
+            len => {
+                let label = packet.get(pos + 1..=pos + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                pos += 1 + usize::from(len);
+            }
+        }
+    }
+    None
+}
+
+/// Where the name starting at `pos` ends
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            // Compressed, the rest of the name is elsewhere
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + usize::from(len)
+        }
+    }
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]))
+}
+
+fn flags(packet: &[u8]) -> u16 {
+    read_u16(packet, 2).unwrap_or_default()
+}
+
+/// Whether `packet` answers the query `id`, about the `qtype` records of `host`
+fn answers(packet: &[u8], id: u16, host: &str, qtype: u16) -> bool {
+    if read_u16(packet, 0) != Some(id) || flags(packet) & FLAG_RESPONSE == 0 || read_u16(packet, 4) != Some(1) {
+        return false;
+    }
+    read_name(packet, 12).is_some_and(|(name, end)| name == host
+        && read_u16(packet, end) == Some(qtype)
+        && read_u16(packet, end + 2) == Some(CLASS_IN))
+}
+
+fn parse_answer(packet: &[u8], host: &str, qtype: u16) -> Result<Vec<IpAddr>, DnsError> {
+    let malformed = || DnsError::Failure(String::from("malformed answer"));
+    let flags = read_u16(packet, 2).ok_or_else(malformed)?;
+    match (flags & 0x000f) as u8 {
+        0 => {},
+        RCODE_NXDOMAIN => return Err(DnsError::NotFound(host.into())),
+        rcode => return Err(DnsError::Failure(format!("server answered with code {rcode}")))
+    }
+    let questions = read_u16(packet, 4).ok_or_else(malformed)?;
+    let answers = read_u16(packet, 6).ok_or_else(malformed)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(packet, pos).ok_or_else(malformed)? + 4;
+    }
+    let mut ips = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(packet, pos).ok_or_else(malformed)?;
+        let rtype = read_u16(packet, pos).ok_or_else(malformed)?;
+        let rdlength = usize::from(read_u16(packet, pos + 8).ok_or_else(malformed)?);
+        let rdata = packet.get(pos + 10..pos + 10 + rdlength).ok_or_else(malformed)?;
+        // Aliases come along, only the addresses matter
+        match (rtype, rdata) {
+            (TYPE_A, &[a, b, c, d]) if qtype == TYPE_A => ips.push(Ipv4Addr::new(a, b, c, d).into()),
+            (TYPE_AAAA, rdata) if qtype == TYPE_AAAA => if let Ok(bytes) = <[u8; 16]>::try_from(rdata) {
+                ips.push(Ipv6Addr::from(bytes).into());
+            },
+            _ => {}
+        }
+        pos += 10 + rdlength;
+    }
+    Ok(ips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    /// The answer of a server to the query `0xabcd` for the A records of
+    /// `www.example.com`, an alias of `example.com`
+    const ALIASED_A: &[u8] = &[
+        0xab, 0xcd, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+        // www.example.com, A, IN
+        0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+        0x00, 0x01, 0x00, 0x01,
+        // www.example.com is an alias of example.com
+        0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x02, 0xc0, 0x10,
+        // example.com has 93.184.216.34
+        0xc0, 0x10, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0x5d, 0xb8, 0xd8, 0x22
+    ];
+
+    /// The answer to the query `0x0102` for the AAAA records of `example.com`
+    const AAAA: &[u8] = &[
+        0x01, 0x02, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+        0x00, 0x1c, 0x00, 0x01,
+        0xc0, 0x0c, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x10,
+        0x26, 0x06, 0x28, 0x00, 0x02, 0x20, 0x00, 0x01, 0x02, 0x48, 0x18, 0x93, 0x25, 0xc8, 0x19, 0x46
+    ];
+
+    /// The answer to the query `0x0304` for the A records of
+    /// `nowhere.example`, which does not exist
+    const NXDOMAIN: &[u8] = &[
+        0x03, 0x04, 0x81, 0x83, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x07, b'n', b'o', b'w', b'h', b'e', b'r', b'e', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x00,
+        0x00, 0x01, 0x00, 0x01
+    ];
+
+    #[test]
+    fn addresses_are_found_past_aliases() {
+        assert!(answers(ALIASED_A, 0xabcd, "www.example.com", TYPE_A));
+        let ips = parse_answer(ALIASED_A, "www.example.com", TYPE_A).unwrap();
+        assert_eq!(ips, [IpAddr::from([93, 184, 216, 34])]);
+    }
+
+    #[test]
+    fn ipv6_addresses_are_found() {
+        assert!(answers(AAAA, 0x0102, "example.com", TYPE_AAAA));
+        let ips = parse_answer(AAAA, "example.com", TYPE_AAAA).unwrap();
+        assert_eq!(ips, ["2606:2800:220:1:248:1893:25c8:1946".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn missing_names_are_not_found() {
+        assert!(answers(NXDOMAIN, 0x0304, "nowhere.example", TYPE_A));
+        assert!(matches!(parse_answer(NXDOMAIN, "nowhere.example", TYPE_A), Err(DnsError::NotFound(_))));
+    }
+
+    #[test]
+    fn answers_to_other_queries_are_not_ours() {
+        // Another query
+        assert!(!answers(ALIASED_A, 0xabce, "www.example.com", TYPE_A));
+        // Another name, or type
+        assert!(!answers(ALIASED_A, 0xabcd, "example.com", TYPE_A));
+        assert!(!answers(ALIASED_A, 0xabcd, "www.example.com", TYPE_AAAA));
+        // Not an answer at all
+        let query = build_query(0xabcd, "www.example.com", TYPE_A).unwrap();
+        assert!(!answers(&query, 0xabcd, "www.example.com", TYPE_A));
+        // Cut short
+        assert!(!answers(&ALIASED_A[..20], 0xabcd, "www.example.com", TYPE_A));
+    }
+
+    #[test]
+    fn names_are_compared_whatever_their_case() {
+        let mut packet = ALIASED_A.to_vec();
+        packet[13..16].copy_from_slice(b"WwW");
+        assert!(answers(&packet, 0xabcd, "www.example.com", TYPE_A));
+    }
+
+    #[test]
+    fn compression_loops_are_malformed() {
+        let mut packet = ALIASED_A[..12].to_vec();
+        packet.extend_from_slice(&[0xc0, 0x0c]);
+        assert_eq!(read_name(&packet, 12), None);
+    }
+
+    /// What a server answers to `query`: the same question, with
+    /// `flags` and an A record for every address of `ips`
+    fn reply(query: &[u8], flags: u16, ips: &[Ipv4Addr]) -> Vec<u8> {
+        let question_end = skip_name(query, 12).unwrap() + 4;
+        let mut packet = query[..2].to_vec();
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(&[0, 1]);
+        packet.extend_from_slice(&u16::try_from(ips.len()).unwrap().to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(&query[12..question_end]);
+        for ip in ips {
+            packet.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            packet.extend_from_slice(&ip.octets());
+        }
+        packet
+    }
+
+    #[tokio::test]
+    async fn truncated_answers_are_asked_again_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap();
+        let socket = UdpSocket::bind(server).await.unwrap();
+        let ips: Vec<Ipv4Addr> = (1..=40).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+        let tcp_ips = ips.clone();
+        // Over UDP, the answers do not fit
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let answer = reply(&buffer[..len], 0x8380, &[]);
+                socket.send_to(&answer, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let len = stream.read_u16().await.unwrap();
+                let mut query = vec![0; usize::from(len)];
+                stream.read_exact(&mut query).await.unwrap();
+                let qtype = read_u16(&query, query.len() - 4).unwrap();
+                let answer = reply(&query, 0x8180, if qtype == TYPE_A { &tcp_ips } else { &[] });
+                let len = u16::try_from(answer.len()).unwrap().to_be_bytes();
+                stream.write_all(&[&len[..], &answer].concat()).await.unwrap();
+            }
+        });
+        let found = query_server(server, "big.example").await.unwrap();
+        let expected: Vec<IpAddr> = ips.into_iter().map(IpAddr::from).collect();
+        assert_eq!(found, expected);
+    }
+
+    fn ips(raw: &[&str]) -> Vec<IpAddr> {
+        raw.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn one_family_is_enough() {
+        let timeout = || Err(DnsError::Timeout("example.com".into()));
+        let found = merge_answers("example.com", Ok(ips(&["93.184.216.34"])), timeout()).unwrap();
+        assert_eq!(found, ips(&["93.184.216.34"]));
+        let found = merge_answers("example.com", timeout(), Ok(ips(&["2606:2800:220:1::1"]))).unwrap();
+        assert_eq!(found, ips(&["2606:2800:220:1::1"]));
+        let found = merge_answers("example.com", Ok(ips(&["93.184.216.34"])), Ok(ips(&["2606:2800:220:1::1"]))).unwrap();
+        assert_eq!(found, ips(&["93.184.216.34", "2606:2800:220:1::1"]));
+    }
+
+    #[test]
+    fn names_without_addresses_exist() {
+        let result = merge_answers("mail.example", Ok(Vec::new()), Ok(Vec::new()));
+        assert!(matches!(result, Err(DnsError::NoData(_))));
+        let failure = || Err(DnsError::Failure("server answered with code 2".into()));
+        assert!(matches!(merge_answers("mail.example", Ok(Vec::new()), failure()), Err(DnsError::Failure(_))));
+    }
+
+    #[test]
+    fn missing_names_win_over_failures() {
+        let missing = || Err(DnsError::NotFound("nowhere.example".into()));
+        let timeout = || Err(DnsError::Timeout("nowhere.example".into()));
+        assert!(matches!(merge_answers("nowhere.example", missing(), timeout()), Err(DnsError::NotFound(_))));
+        assert!(matches!(merge_answers("nowhere.example", timeout(), missing()), Err(DnsError::NotFound(_))));
+        assert!(matches!(merge_answers("nowhere.example", timeout(), timeout()), Err(DnsError::Timeout(_))));
+    }
+
+    #[test]
+    fn the_system_resolves_names() {
+        let found = getaddrinfo("localhost", 8080).unwrap();
+        assert!(found.contains(&SocketAddr::from((Ipv4Addr::LOCALHOST, 8080))), "{found:?}");
+    }
+
+    #[test]
+    fn the_system_is_not_asked_about_invalid_names() {
+        let err = getaddrinfo("local\0host", 80).unwrap_err();
+        assert!(matches!(err.get_ref().and_then(|e| e.downcast_ref()), Some(DnsError::Failure(_))));
+    }
+}
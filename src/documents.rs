@@ -0,0 +1,431 @@
+//! Links of documents
+//!
+//! Some lists of URLs are documents in their own right: a README, the
+//! bookmarks exported by a browser, or the OPML list of a feed reader.
+//! Each format says where its links are in its own way, so each has a
+//! parser of its own. Links that are relative are resolved against a base
+//! URL when there is one, and reported as unparseable when there is not.
+
+use quick_xml::{
+    events::{
+        BytesStart,
+        Event
+    },
+    Reader
+};
+use regex::Regex;
+use reqwest::Url;
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet
+    },
+    str::FromStr,
+    sync::OnceLock
+};
+
+use crate::{
+    dto::UrlEntry,
+    links::{
+        references,
+        unescape
+    },
+    upload::essence
+};
+
+/// The kinds of documents links are extracted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Markdown,
+    Html,
+    /// Netscape bookmark files, as exported by browsers
+    Bookmarks,
+    Opml
+}
+
+impl FromStr for DocumentFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "bookmarks" => Ok(Self::Bookmarks),
+            "opml" => Ok(Self::Opml),
+            _ => Err(format!("unknown document format \"{s}\", expected \"markdown\", \"html\", \"bookmarks\" or \"opml\""))
+        }
+    }
+}
+
+const BOOKMARKS_DOCTYPE: &[u8] = b"NETSCAPE-Bookmark-file-1";
+
+impl DocumentFormat {
+    /// The format of `body`, going by its media type, then by the
+    /// extension of its file name. Bookmark files are HTML, and told
+    /// apart by their doctype.
+    pub fn detect(content_type: Option<&str>, file_name: Option<&str>, body: &[u8]) -> Option<Self> {
+        let html = || if contains(body, BOOKMARKS_DOCTYPE) {
+            Self::Bookmarks
+        } else {
+            Self::Html
+        };
+        match content_type.map(essence).as_deref() {
+            Some("text/markdown" | "text/x-markdown") => Some(Self::Markdown),
+            Some("text/html" | "application/xhtml+xml") => Some(html()),
+            Some("text/x-opml" | "application/xml" | "text/xml") => Some(Self::Opml),
+            None | Some("text/plain" | "application/octet-stream") => {
+                let extension = file_name?.rsplit_once('.')?.1.to_ascii_lowercase();
+                match extension.as_str() {
+                    "md" | "markdown" => Some(Self::Markdown),
+                    "html" | "htm" => Some(html()),
+                    "opml" | "xml" => Some(Self::Opml),
+                    _ => None
+                }
+            },
+            _ => None
+        }
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// What came out of a document
+#[derive(Debug, Default)]
+pub struct Extracted {
+    entries: Vec<UrlEntry>,
+    unparseable: Vec<String>,
+    seen: HashSet<Url>
+}
+
+impl Extracted {
+    /// Add the link written as `raw`, unless it goes nowhere we can check
+    fn add(&mut self, raw: &str, base: Option<&Url>, labels: HashMap<String, String>) {
+        let raw = raw.trim();
+        // Links within the document, and empty ones
+        if raw.is_empty() || raw.starts_with('#') {
+            return;
+        }
+        let parsed = match Url::parse(raw) {
+            Err(url::ParseError::RelativeUrlWithoutBase) => base.map(|base| base.join(raw)),
+            parsed => Some(parsed)
+        };
+        let Some(Ok(mut url)) = parsed else {
+            if !self.unparseable.iter().any(|seen| seen == raw) {
+                self.unparseable.push(raw.into());
+            }
+            return;
+        };
+        // Like `mailto:` links
+        if !matches!(url.scheme(), "http" | "https") {
+            return;
+        }
+        url.set_fragment(None);
+        if self.seen.insert(url.clone()) {
+            self.entries.push(UrlEntry::labelled(url.into(), labels));
+        }
+    }
+
+    /// Add the links of `text`, a document in `format`, with
+    /// relative ones resolved against `base`
+    pub fn read(&mut self, format: DocumentFormat, text: &str, base: Option<&Url>) -> Result<(), String> {
+        match format {
+            DocumentFormat::Markdown => parse_markdown(text, base, self),
+            DocumentFormat::Html => parse_html(text.as_bytes(), base, self),
+            DocumentFormat::Bookmarks => parse_bookmarks(text.as_bytes(), base, self),
+            DocumentFormat::Opml => parse_opml(text, base, self)?
+        }
+        Ok(())
+    }
+
+    pub fn explode(self) -> (Vec<UrlEntry>, Vec<String>) {
+        (self.entries, self.unparseable)
+    }
+}
+
+fn code_regex() -> &'static Regex {
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    CODE.get_or_init(|| Regex::new(r"`+[^`]*`+").unwrap())
+}
+
+fn markdown_regex() -> &'static Regex {
+    static MARKDOWN: OnceLock<Regex> = OnceLock::new();
+    // Inline links and images, reference definitions, autolinks,
+    // and bare URLs, in that order
+    MARKDOWN.get_or_init(|| Regex::new(concat!(
+        r#"\]\(\s*(?:<([^>]*)>|((?:[^()\s]|\([^()\s]*\))+))(?:\s+(?:"[^"]*"|'[^']*'|\([^)]*\)))?\s*\)"#,
+        r"|^\s{0,3}\[[^\]]+\]:\s*(?:<([^>]*)>|(\S+))",
+        r"|<([a-zA-Z][a-zA-Z0-9+.-]*:[^\s<>]+)>",
+        r"|(https?://[^\s<>()\[\]]+)"
+    )).unwrap())
+}
+
+/// Links of a Markdown document, leaving code out. HTML found along the
+/// way is searched too.
+fn parse_markdown(text: &str, base: Option<&Url>, extracted: &mut Extracted) {
+    let mut fence: Option<&str> = None;
+    let mut prose = String::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|marker| trimmed.starts_with(marker)) {
+            fence = Some(marker);
+            continue;
+        }
+        let line = code_regex().replace_all(line, "");
+        for link in markdown_regex().captures_iter(&line) {
+            let Some(raw) = (1..=6).find_map(|i| link.get(i)) else {
+                continue;
+            };
+            let mut raw = raw.as_str();
+            // Bare URLs end before the punctuation that follows them
+            if link.get(6).is_some() {
+                raw = raw.trim_end_matches(['.', ',', ':', ';', '!', '?', '*', '_', '\'', '"']);
+            }
+            extracted.add(raw, base, HashMap::new());
+        }
+        prose.push_str(&line);
+        prose.push('\n');
+    }
+    parse_html(prose.as_bytes(), base, extracted);
+}
+
+/// Links, images, scripts and stylesheets of an HTML page
+fn parse_html(body: &[u8], base: Option<&Url>, extracted: &mut Extracted) {
+    let references = references(body);
+    // Only the first `<base>` counts, wherever the links are
+    let base = references.iter()
+        .find(|(name, _)| name == "base")
+        .and_then(|(_, value)| base.map_or_else(|| Url::parse(value), |base| base.join(value)).ok())
+        .or_else(|| base.cloned());
+    for (name, value) in references {
+        if name != "base" {
+            extracted.add(&value, base.as_ref(), HashMap::new());
+        }
+    }
+}
+
+fn bookmark_regex() -> &'static Regex {
+    static BOOKMARK: OnceLock<Regex> = OnceLock::new();
+    BOOKMARK.get_or_init(|| Regex::new(
+        r#"(?is)<h3\b[^>]*>(.*?)</h3>|<a\b[^>]*?\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))[^>]*>(.*?)</a>|<(/?)dl\b"#
+    ).unwrap())
+}
+
+fn markup_regex() -> &'static Regex {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    MARKUP.get_or_init(|| Regex::new(r"<[^>]*>").unwrap())
+}
+
+/// The text of some HTML, without its markup
+fn html_text(html: &str) -> String {
+    unescape(&markup_regex().replace_all(html, ""))
+}
+
+/// The path to a bookmark or a feed, leaving out nameless folders
+fn folder(folders: &[String]) -> Option<String> {
+    let names: Vec<&str> = folders.iter()
+        .map(String::as_str)
+        .filter(|name| !name.is_empty())
+        .collect();
+    (!names.is_empty()).then(|| names.join(" / "))
+}
+
+/// Bookmarks, labelled with their title and the folder they are in
+fn parse_bookmarks(body: &[u8], base: Option<&Url>, extracted: &mut Extracted) {
+    let text = String::from_utf8_lossy(body);
+    let mut folders: Vec<String> = Vec::new();
+    // The heading of the folder about to open
+    let mut heading = None;
+    for token in bookmark_regex().captures_iter(&text) {
+        if let Some(title) = token.get(1) {
+            heading = Some(html_text(title.as_str()));
+        } else if let Some(href) = token.get(2).or_else(|| token.get(3)).or_else(|| token.get(4)) {
+            let mut labels = HashMap::new();
+            let title = html_text(token.get(5).map_or("", |title| title.as_str()));
+            if !title.is_empty() {
+                labels.insert(String::from("title"), title);
+            }
+            if let Some(folder) = folder(&folders) {
+                labels.insert(String::from("folder"), folder);
+            }
+            extracted.add(&unescape(href.as_str()), base, labels);
+        } else if token.get(6).is_some_and(|slash| slash.as_str().is_empty()) {
+            folders.push(heading.take().unwrap_or_default());
+        } else {
+            folders.pop();
+        }
+    }
+}
+
+/// The value of the attribute of `tag` named `name`
+fn xml_attribute(tag: &BytesStart, name: &str) -> Option<String> {
+    tag.attributes()
+        .filter_map(Result::ok)
+        .find(|attribute| attribute.key.local_name().as_ref().eq_ignore_ascii_case(name.as_bytes()))
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Feeds and sites of an OPML outline, labelled with their
+/// title and the outlines they are in
+fn parse_opml(text: &str, base: Option<&Url>, extracted: &mut Extracted) -> Result<(), String> {
+    let mut reader = Reader::from_str(text);
+    let mut folders: Vec<String> = Vec::new();
+    loop {
+        let event = reader.read_event()
+            .map_err(|e| format!("invalid XML at byte {}: {e}", reader.buffer_position()))?;
+        let (tag, opens) = match &event {
+            Event::Start(tag) => (tag, true),
+            Event::Empty(tag) => (tag, false),
+            Event::End(tag) => {
+                if tag.local_name().as_ref() == b"outline" {
+                    folders.pop();
+                }
+                continue;
+            },
+            Event::Eof => break,
+            _ => continue
+        };
+        if tag.local_name().as_ref() != b"outline" {
+            continue;
+        }
+        let title = xml_attribute(tag, "title").or_else(|| xml_attribute(tag, "text"));
+        for name in ["xmlUrl", "htmlUrl", "url"] {
+            if let Some(url) = xml_attribute(tag, name) {
+                let mut labels = HashMap::new();
+                if let Some(title) = &title {
+                    labels.insert(String::from("title"), title.clone());
+                }
+                if let Some(folder) = folder(&folders) {
+                    labels.insert(String::from("folder"), folder);
+                }
+                extracted.add(&url, base, labels);
+            }
+        }
+        if opens {
+            folders.push(title.unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(format: DocumentFormat, text: &str, base: Option<&str>) -> Extracted {
+        let base = base.map(|base| Url::parse(base).unwrap());
+        let mut extracted = Extracted::default();
+        extracted.read(format, text, base.as_ref()).unwrap();
+        extracted
+    }
+
+    fn urls(extracted: Extracted) -> Vec<String> {
+        extracted.explode().0.iter().map(|entry| entry.get_url().to_string()).collect()
+    }
+
+    fn labels(entry: &UrlEntry) -> Vec<(&str, &str)> {
+        let mut labels: Vec<(&str, &str)> = entry.get_labels().into_iter()
+            .flatten()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        labels.sort_unstable();
+        labels
+    }
+
+    #[test]
+    fn markdown_autolinks_sit_alongside_inline_links() {
+        let text = concat!(
+            "See [the docs](https://a.test/docs \"Docs\") or <https://b.test/>, ",
+            "then ![logo](<img/logo 1.png>) and https://c.test/page.\n",
+            "[ref]: https://d.test/ref\n",
+            "Mail <mailto:me@a.test>, not `https://code.test/` nor [top](#top).\n",
+            "```\nhttps://fenced.test/\n```\n",
+            "Again [the docs](https://a.test/docs#intro) and <a href=\"/page\">x</a>\n"
+        );
+        assert_eq!(urls(read(DocumentFormat::Markdown, text, Some("https://a.test/readme"))), [
+            "https://a.test/docs",
+            "https://b.test/",
+            "https://a.test/img/logo%201.png",
+            "https://c.test/page",
+            "https://d.test/ref",
+            "https://a.test/page"
+        ]);
+    }
+
+    #[test]
+    fn relative_links_need_a_base() {
+        let (entries, unparseable) = read(DocumentFormat::Markdown, "[x](intro) [y](https://a.test/)", None).explode();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(unparseable, ["intro"]);
+    }
+
+    #[test]
+    fn html_links_resolve_against_the_first_base() {
+        let text = r#"<a href="one"><base href="https://a.test/v2/"><img src="two.png"><base href="/v3/">"#;
+        assert_eq!(urls(read(DocumentFormat::Html, text, None)), [
+            "https://a.test/v2/one",
+            "https://a.test/v2/two.png"
+        ]);
+    }
+
+    #[test]
+    fn bookmarks_carry_their_title_and_folder() {
+        let text = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<DL><p>
+    <DT><H3>Work</H3>
+    <DL><p>
+        <DT><H3>Tools &amp; docs</H3>
+        <DL><p>
+            <DT><A HREF="https://a.test/" ADD_DATE="1">A <b>site</b></A>
+        </DL><p>
+        <DT><A HREF='https://b.test/'></A>
+    </DL><p>
+    <DT><A HREF=https://c.test/>C</A>
+</DL><p>"#;
+        let (entries, _) = read(DocumentFormat::Bookmarks, text, None).explode();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(labels(&entries[0]), [("folder", "Work / Tools & docs"), ("title", "A site")]);
+        assert_eq!(labels(&entries[1]), [("folder", "Work")]);
+        assert_eq!(labels(&entries[2]), [("title", "C")]);
+    }
+
+    #[test]
+    fn opml_outlines_are_folders() {
+        let text = r#"<?xml version="1.0"?>
+<opml version="2.0"><body>
+    <outline text="News">
+        <outline text="Site A" xmlUrl="https://a.test/feed" htmlUrl="https://a.test/"/>
+    </outline>
+    <outline title="Site B" type="rss" xmlUrl="feed.xml"></outline>
+</body></opml>"#;
+        let (entries, _) = read(DocumentFormat::Opml, text, Some("https://b.test/")).explode();
+        let urls: Vec<&str> = entries.iter().map(UrlEntry::get_url).collect();
+        assert_eq!(urls, ["https://a.test/feed", "https://a.test/", "https://b.test/feed.xml"]);
+        assert_eq!(labels(&entries[0]), [("folder", "News"), ("title", "Site A")]);
+        assert_eq!(labels(&entries[2]), [("title", "Site B")]);
+    }
+
+    #[test]
+    fn broken_opml_is_refused() {
+        let mut extracted = Extracted::default();
+        assert!(extracted.read(DocumentFormat::Opml, "<opml><body></opml>", None).is_err());
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(DocumentFormat::detect(Some("text/markdown; charset=utf-8"), None, b""), Some(DocumentFormat::Markdown));
+        assert_eq!(DocumentFormat::detect(Some("text/html"), None, b"<!DOCTYPE netscape-bookmark-file-1>"), Some(DocumentFormat::Bookmarks));
+        assert_eq!(DocumentFormat::detect(None, Some("feeds.OPML"), b""), Some(DocumentFormat::Opml));
+        assert_eq!(DocumentFormat::detect(Some("text/plain"), Some("list.txt"), b""), None);
+    }
+}
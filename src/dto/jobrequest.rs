@@ -0,0 +1,438 @@
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum JobRequest {
+    Legacy(Vec<String>),
+    Detailed {
+        urls: Vec<UrlEntry>,
+        #[serde(default)]
+        options: Box<JobOptions>
+    }
+}
+
+impl JobRequest {
+    pub fn new(urls: Vec<UrlEntry>, options: JobOptions) -> Self {
+        Self::Detailed { urls, options: Box::new(options) }
+    }
+
+    /// Plain lists of URLs get the replies they always got
+    pub const fn is_legacy(&self) -> bool {
+        matches!(self, Self::Legacy(_))
+    }
+
+    pub fn explode(self) -> (Vec<UrlEntry>, JobOptions) {
+        match self {
+            Self::Legacy(urls) => (
+                urls.into_iter().map(UrlEntry::Plain).collect(),
+                JobOptions::default()
+            ),
+            Self::Detailed { urls, options } => (urls, *options)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum UrlEntry {
+    Plain(String),
+    Detailed(Box<UrlSpec>)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UrlSpec {
+    url: String,
+    #[serde(default)]
+    sitemap: bool,
+    mode: Option<String>,
+    expect: Option<Expect>,
+    checks: Option<Checks>,
+    #[serde(default)]
+    labels: HashMap<String, String>
+}
+
+impl UrlEntry {
+    /// An entry for `url`, carrying `labels` if there are any
+    pub fn labelled(url: String, labels: HashMap<String, String>) -> Self {
+        if labels.is_empty() {
+            return Self::Plain(url);
+        }
+        Self::Detailed(Box::new(UrlSpec {
+            url,
+            sitemap: false,
+            mode: None,
+            expect: None,
+            checks: None,
+            labels
+        }))
+    }
+
+    pub fn get_url(&self) -> &str {
+        match self {
+            Self::Plain(url) => url,
+            Self::Detailed(spec) => &spec.url
+        }
+    }
+
+    pub fn get_mode(&self) -> Option<&str> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Detailed(spec) => spec.mode.as_deref()
+        }
+    }
+
+    pub fn is_sitemap(&self) -> bool {
+        match self {
+            Self::Plain(_) => false,
+            Self::Detailed(spec) => spec.sitemap
+        }
+    }
+
+    pub fn get_expect(&self) -> Option<&Expect> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Detailed(spec) => spec.expect.as_ref()
+        }
+    }
+
+    pub fn get_checks(&self) -> Option<&Checks> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Detailed(spec) => spec.checks.as_ref()
+        }
+    }
+
+    pub fn get_labels(&self) -> Option<&HashMap<String, String>> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Detailed(spec) => Some(&spec.labels)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StatusSpec {
+    Code(u16),
+    Class(String)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>)
+}
+
+impl<T> OneOrMany<T> {
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            Self::One(one) => std::slice::from_ref(one),
+            Self::Many(many) => many
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Expect {
+    status: Option<OneOrMany<StatusSpec>>,
+    redirect_status: Option<OneOrMany<StatusSpec>>,
+    final_url: Option<String>,
+    max_latency_ms: Option<u64>
+}
+
+impl Expect {
+    pub fn get_status(&self) -> &[StatusSpec] {
+        self.status.as_ref().map_or(&[], OneOrMany::as_slice)
+    }
+
+    pub fn get_redirect_status(&self) -> &[StatusSpec] {
+        self.redirect_status.as_ref().map_or(&[], OneOrMany::as_slice)
+    }
+
+    pub fn get_final_url(&self) -> Option<&str> {
+        self.final_url.as_deref()
+    }
+
+    pub const fn get_max_latency_ms(&self) -> Option<u64> {
+        self.max_latency_ms
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Checks {
+    contains: Option<OneOrMany<String>>,
+    not_contains: Option<OneOrMany<String>>,
+    regex: Option<OneOrMany<String>>,
+    #[serde(default)]
+    json: HashMap<String, serde_json::Value>,
+    content_type: Option<String>
+}
+
+impl Checks {
+    pub fn get_contains(&self) -> &[String] {
+        self.contains.as_ref().map_or(&[], OneOrMany::as_slice)
+    }
+
+    pub fn get_not_contains(&self) -> &[String] {
+        self.not_contains.as_ref().map_or(&[], OneOrMany::as_slice)
+    }
+
+    pub fn get_regex(&self) -> &[String] {
+        self.regex.as_ref().map_or(&[], OneOrMany::as_slice)
+    }
+
+    pub const fn get_json(&self) -> &HashMap<String, serde_json::Value> {
+        &self.json
+    }
+
+    pub fn get_content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeaderSpec {
+    name: String,
+    value: Secret,
+    #[serde(default)]
+    hosts: Vec<String>
+}
+
+impl HeaderSpec {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub const fn get_value(&self) -> &Secret {
+        &self.value
+    }
+
+    pub fn get_hosts(&self) -> &[String] {
+        &self.hosts
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BasicAuthSpec {
+    user: String,
+    password: Secret
+}
+
+impl BasicAuthSpec {
+    pub fn get_user(&self) -> &str {
+        &self.user
+    }
+
+    pub const fn get_password(&self) -> &Secret {
+        &self.password
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthSpec {
+    hosts: Vec<String>,
+    basic: Option<BasicAuthSpec>,
+    bearer: Option<Secret>
+}
+
+impl AuthSpec {
+    pub fn get_hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    pub const fn get_basic(&self) -> Option<&BasicAuthSpec> {
+        self.basic.as_ref()
+    }
+
+    pub const fn get_bearer(&self) -> Option<&Secret> {
+        self.bearer.as_ref()
+    }
+}
+
+/// Either `"default"`, `"direct"`, or a proxy of its own
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ProxySpec {
+    Named(String),
+    Custom {
+        // May carry credentials
+        url: Secret,
+        #[serde(default)]
+        no_proxy: Vec<String>
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CrawlSpec {
+    max_depth: Option<usize>,
+    max_pages: Option<usize>,
+    #[serde(default)]
+    scope: Vec<String>
+}
+
+impl CrawlSpec {
+    pub const fn get_max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub const fn get_max_pages(&self) -> Option<usize> {
+        self.max_pages
+    }
+
+    pub fn get_scope(&self) -> &[String] {
+        &self.scope
+    }
+}
+
+// Every switch users can flip is a field of the wire format
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct JobOptions {
+    follow_redirects: bool,
+    max_redirects: usize,
+    retries: u32,
+    detect_soft_404: bool,
+    sort_query: bool,
+    no_cache: bool,
+    conditional: bool,
+    cert_expiry_days: Option<u32>,
+    insecure: bool,
+    client_identity: Option<String>,
+    mode: Option<String>,
+    dual_stack: bool,
+    every_address: bool,
+    resolve: HashMap<String, OneOrMany<String>>,
+    seeds: bool,
+    crawl: Option<CrawlSpec>,
+    ignore_robots: bool,
+    expect: Option<Expect>,
+    checks: Option<Checks>,
+    headers: Vec<HeaderSpec>,
+    cookies: Vec<HeaderSpec>,
+    auth: Vec<AuthSpec>,
+    proxy: Option<ProxySpec>
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            follow_redirects: true,
+            max_redirects: 10,
+            retries: 0,
+            detect_soft_404: false,
+            sort_query: false,
+            no_cache: false,
+            conditional: false,
+            cert_expiry_days: None,
+            insecure: false,
+            client_identity: None,
+            mode: None,
+            dual_stack: false,
+            every_address: false,
+            resolve: HashMap::new(),
+            seeds: false,
+            crawl: None,
+            ignore_robots: false,
+            expect: None,
+            checks: None,
+            headers: Vec::new(),
+            cookies: Vec::new(),
+            auth: Vec::new(),
+            proxy: None
+        }
+    }
+}
+
+impl JobOptions {
+    pub const fn get_follow_redirects(&self) -> bool {
+        self.follow_redirects
+    }
+
+    pub const fn get_max_redirects(&self) -> usize {
+        self.max_redirects
+    }
+
+    pub const fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub const fn get_detect_soft_404(&self) -> bool {
+        self.detect_soft_404
+    }
+
+    pub const fn get_sort_query(&self) -> bool {
+        self.sort_query
+    }
+
+    pub const fn get_seeds(&self) -> bool {
+        self.seeds
+    }
+
+    pub const fn get_crawl(&self) -> Option<&CrawlSpec> {
+        self.crawl.as_ref()
+    }
+
+    pub const fn get_ignore_robots(&self) -> bool {
+        self.ignore_robots
+    }
+
+    pub const fn get_no_cache(&self) -> bool {
+        self.no_cache
+    }
+
+    pub const fn get_conditional(&self) -> bool {
+        self.conditional
+    }
+
+    pub const fn get_cert_expiry_days(&self) -> Option<u32> {
+        self.cert_expiry_days
+    }
+
+    pub const fn get_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    pub fn get_client_identity(&self) -> Option<&str> {
+        self.client_identity.as_deref()
+    }
+
+    pub fn get_mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+
+    pub const fn get_dual_stack(&self) -> bool {
+        self.dual_stack
+    }
+
+    pub const fn get_every_address(&self) -> bool {
+        self.every_address
+    }
+
+    pub const fn get_resolve(&self) -> &HashMap<String, OneOrMany<String>> {
+        &self.resolve
+    }
+
+    pub const fn get_expect(&self) -> Option<&Expect> {
+        self.expect.as_ref()
+    }
+
+    pub const fn get_checks(&self) -> Option<&Checks> {
+        self.checks.as_ref()
+    }
+
+    pub fn get_headers(&self) -> &[HeaderSpec] {
+        &self.headers
+    }
+
+    pub fn get_cookies(&self) -> &[HeaderSpec] {
+        &self.cookies
+    }
+
+    pub fn get_auth(&self) -> &[AuthSpec] {
+        &self.auth
+    }
+
+    pub const fn get_proxy(&self) -> Option<&ProxySpec> {
+        self.proxy.as_ref()
+    }
+}
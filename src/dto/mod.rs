@@ -1,5 +1,6 @@
 //! Data Transfer Objects
 
+use reqwest::Url;
 use serde::{
     Deserialize,
     Serialize
@@ -7,5 +8,30 @@ use serde::{
 
 use std::collections::HashMap;
 
+use crate::{
+    checks::CheckOutcome,
+    expect::Verdict,
+    extras::Secret,
+    messages::{
+        DownloadResult,
+        FetchReport,
+        LatencySummary,
+        RouteReport,
+        StatusReplyMessage,
+        Timings,
+        VerdictSummary
+    },
+    soft404::Soft404Report,
+    tls::{
+        CertInfo,
+        TlsReport
+    }
+};
+
+include!("jobrequest.rs");
 include!("loginrequest.rs");
+include!("redirectchain.rs");
 include!("statusreply.rs");
+include!("submissionreply.rs");
+include!("uploadquery.rs");
+include!("urlresult.rs");
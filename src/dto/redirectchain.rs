@@ -0,0 +1,23 @@
+#[derive(Serialize)]
+pub struct RedirectHop {
+    status: u16,
+    location: String
+}
+
+impl RedirectHop {
+    pub fn new(status: u16, location: String) -> Self {
+        Self { status, location }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RedirectChain {
+    hops: Vec<RedirectHop>,
+    final_url: String
+}
+
+impl RedirectChain {
+    pub fn new(hops: Vec<RedirectHop>, final_url: String) -> Self {
+        Self { hops, final_url }
+    }
+}
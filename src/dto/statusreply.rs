@@ -1,12 +1,17 @@
-
 #[derive(Serialize)]
 pub struct StatusReply {
     finished: bool,
-    results: HashMap<String, i32>
+    results: HashMap<String, i32>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    redirects: HashMap<String, RedirectChain>
 }
 
 impl StatusReply {
-    pub fn new(finished: bool, results: HashMap<String, i32>) -> Self {
-        Self { finished, results }
+    pub fn new(
+        finished: bool,
+        results: HashMap<String, i32>,
+        redirects: HashMap<String, RedirectChain>
+    ) -> Self {
+        Self { finished, results, redirects }
     }
 }
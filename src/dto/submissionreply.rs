@@ -0,0 +1,29 @@
+#[derive(Serialize)]
+pub struct SubmissionReply {
+    uuid: String,
+    accepted: usize,
+    merged: usize
+}
+
+impl SubmissionReply {
+    pub fn new(uuid: String, accepted: usize, merged: usize) -> Self {
+        Self { uuid, accepted, merged }
+    }
+}
+
+/// Reply to a document submission
+#[derive(Serialize)]
+pub struct DocumentReply {
+    uuid: String,
+    extracted: usize,
+    accepted: usize,
+    merged: usize,
+    // Links that could not be turned into URLs, as written
+    unparseable: Vec<String>
+}
+
+impl DocumentReply {
+    pub fn new(uuid: String, extracted: usize, accepted: usize, merged: usize, unparseable: Vec<String>) -> Self {
+        Self { uuid, extracted, accepted, merged, unparseable }
+    }
+}
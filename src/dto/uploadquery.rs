@@ -0,0 +1,41 @@
+/// How to read a URL list sent as CSV, given in the query string
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct UploadQuery {
+    url_column: Option<String>,
+    delimiter: Option<String>,
+    header: Option<bool>
+}
+
+impl UploadQuery {
+    pub fn get_url_column(&self) -> Option<&str> {
+        self.url_column.as_deref()
+    }
+
+    pub fn get_delimiter(&self) -> Option<&str> {
+        self.delimiter.as_deref()
+    }
+
+    pub const fn get_header(&self) -> Option<bool> {
+        self.header
+    }
+}
+
+/// What a document is and where its relative links lead,
+/// given in the query string
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DocumentQuery {
+    format: Option<String>,
+    base: Option<String>
+}
+
+impl DocumentQuery {
+    pub fn get_format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    pub fn get_base(&self) -> Option<&str> {
+        self.base.as_deref()
+    }
+}
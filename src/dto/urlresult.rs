@@ -0,0 +1,322 @@
+// The unit is part of the wire format
+#[allow(clippy::struct_field_names)]
+#[derive(Serialize)]
+pub struct Timing {
+    // Those three are left out when the fetch reused a connection
+    dns_ms: Option<u128>,
+    connect_ms: Option<u128>,
+    tls_ms: Option<u128>,
+    ttfb_ms: Option<u128>,
+    total_ms: u128
+}
+
+impl From<&Timings> for Timing {
+    fn from(timings: &Timings) -> Self {
+        Self {
+            dns_ms: timings.get_dns().map(|d| d.as_millis()),
+            connect_ms: timings.get_connect().map(|d| d.as_millis()),
+            tls_ms: timings.get_tls().map(|d| d.as_millis()),
+            ttfb_ms: timings.get_ttfb().map(|d| d.as_millis()),
+            total_ms: timings.get_total().as_millis()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UrlVerdict {
+    pass: bool,
+    failures: Vec<String>
+}
+
+impl From<&Verdict> for UrlVerdict {
+    fn from(verdict: &Verdict) -> Self {
+        Self {
+            pass: verdict.passed(),
+            failures: verdict.get_failures().to_vec()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CheckReport {
+    pass: bool,
+    failures: Vec<String>
+}
+
+impl From<&CheckOutcome> for CheckReport {
+    fn from(outcome: &CheckOutcome) -> Self {
+        Self {
+            pass: outcome.passed(),
+            failures: outcome.get_failures().to_vec()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Soft404 {
+    likely: bool,
+    similarity: f64,
+    same_title: bool,
+    length_ratio: f64
+}
+
+impl From<&Soft404Report> for Soft404 {
+    fn from(report: &Soft404Report) -> Self {
+        Self {
+            likely: report.is_likely(),
+            similarity: report.get_similarity(),
+            same_title: report.has_same_title(),
+            length_ratio: report.get_length_ratio()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Certificate {
+    subject: String,
+    issuer: String,
+    sans: Vec<String>,
+    not_after: Option<String>
+}
+
+impl From<&CertInfo> for Certificate {
+    fn from(cert: &CertInfo) -> Self {
+        Self {
+            subject: cert.get_subject().into(),
+            issuer: cert.get_issuer().into(),
+            sans: cert.get_sans().to_vec(),
+            not_after: cert.get_not_after().map(|t| t.to_rfc3339())
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TlsResult {
+    // Where the connection that was shown the certificates went
+    address: String,
+    days_left: Option<i64>,
+    expiring: bool,
+    hostname_match: bool,
+    verify_error: Option<String>,
+    chain: Vec<Certificate>
+}
+
+impl From<&TlsReport> for TlsResult {
+    fn from(tls: &TlsReport) -> Self {
+        Self {
+            address: tls.get_address().to_string(),
+            days_left: tls.days_left(),
+            expiring: tls.is_expiring(),
+            hostname_match: tls.has_hostname_match(),
+            verify_error: tls.get_verify_error().map(String::from),
+            chain: tls.get_chain().iter().map(Certificate::from).collect()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RouteResult {
+    family: &'static str,
+    address: Option<String>,
+    status: Option<u16>,
+    error: Option<&'static str>,
+    message: Option<String>,
+    total_ms: u128
+}
+
+impl From<&RouteReport> for RouteResult {
+    fn from(route: &RouteReport) -> Self {
+        let result = route.get_result();
+        Self {
+            family: route.get_family().as_str(),
+            address: route.get_address().map(|address| address.to_string()),
+            status: match result {
+                DownloadResult::Fetched(status) => Some(status.as_u16()),
+                _ => None
+            },
+            error: result.error_kind(),
+            message: route.get_message().map(String::from),
+            total_ms: route.get_total().as_millis()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UrlResult {
+    mode: &'static str,
+    status: Option<u16>,
+    error: Option<&'static str>,
+    message: Option<String>,
+    timing: Timing,
+    final_url: String,
+    redirects: Vec<RedirectHop>,
+    content_type: Option<String>,
+    content_length: Option<u64>,
+    attempts: u32,
+    fetched_at: String,
+    cached: bool,
+    unchanged: bool,
+    tls: Option<TlsResult>,
+    address: Option<String>,
+    addresses: Option<Vec<String>>,
+    routes: Option<Vec<RouteResult>>,
+    linked_from: Option<Vec<String>>,
+    lastmod: Option<String>,
+    labels: Option<HashMap<String, String>>,
+    checks: Option<CheckReport>,
+    soft_404: Option<Soft404>,
+    verdict: Option<UrlVerdict>
+}
+
+impl From<FetchReport> for UrlResult {
+    fn from(report: FetchReport) -> Self {
+        let result = report.get_result();
+        Self {
+            mode: report.get_mode().as_str(),
+            status: match result {
+                DownloadResult::Fetched(status) => Some(status.as_u16()),
+                _ => None
+            },
+            error: result.error_kind(),
+            message: report.get_message().map(String::from),
+            timing: report.get_timings().into(),
+            final_url: report.get_final_url().to_string(),
+            redirects: report.get_redirects()
+                .iter()
+                .map(|hop| RedirectHop::new(
+                    hop.get_status().as_u16(),
+                    hop.get_location().to_string()
+                ))
+                .collect(),
+            content_type: report.get_content_type().map(String::from),
+            content_length: report.get_content_length(),
+            attempts: report.get_attempts(),
+            fetched_at: report.get_fetched_at().to_rfc3339(),
+            cached: report.is_cached(),
+            unchanged: report.is_unchanged(),
+            tls: report.get_tls().map(TlsResult::from),
+            address: report.get_address().map(|address| address.to_string()),
+            addresses: (!report.get_addresses().is_empty()).then(|| report.get_addresses()
+                .iter()
+                .map(ToString::to_string)
+                .collect()),
+            routes: (!report.get_routes().is_empty())
+                .then(|| report.get_routes().iter().map(RouteResult::from).collect()),
+            linked_from: (!report.get_linked_from().is_empty()).then(|| report.get_linked_from()
+                .iter()
+                .map(ToString::to_string)
+                .collect()),
+            lastmod: report.get_lastmod().map(String::from),
+            labels: (!report.get_labels().is_empty()).then(|| report.get_labels().clone()),
+            checks: report.get_checks().map(CheckReport::from),
+            soft_404: report.get_soft_404().map(Soft404::from),
+            verdict: report.get_verdict().map(UrlVerdict::from)
+        }
+    }
+}
+
+#[allow(clippy::struct_field_names)]
+#[derive(Serialize)]
+pub struct Percentiles {
+    p50_ms: u128,
+    p95_ms: u128,
+    max_ms: u128
+}
+
+impl From<LatencySummary> for Percentiles {
+    fn from(summary: LatencySummary) -> Self {
+        Self {
+            p50_ms: summary.get_p50().as_millis(),
+            p95_ms: summary.get_p95().as_millis(),
+            max_ms: summary.get_max().as_millis()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct JobTiming {
+    total: Option<Percentiles>,
+    ttfb: Option<Percentiles>
+}
+
+#[derive(Serialize)]
+pub struct JobVerdictReply {
+    verdict: &'static str,
+    passed: usize,
+    failed: usize
+}
+
+impl From<VerdictSummary> for JobVerdictReply {
+    fn from(summary: VerdictSummary) -> Self {
+        Self {
+            verdict: summary.get_verdict().as_str(),
+            passed: summary.get_passed(),
+            failed: summary.get_failed()
+        }
+    }
+}
+
+/// A link that does not work, and where it was found
+#[derive(Serialize)]
+pub struct BrokenLink {
+    url: String,
+    status: Option<u16>,
+    error: Option<&'static str>,
+    linked_from: Vec<String>
+}
+
+impl BrokenLink {
+    fn of(url: &Url, report: &FetchReport) -> Option<Self> {
+        if report.get_linked_from().is_empty() {
+            return None;
+        }
+        let result = report.get_result();
+        let status = match result {
+            DownloadResult::Fetched(status) if status.is_client_error() || status.is_server_error() => Some(status.as_u16()),
+            DownloadResult::Fetched(_) | DownloadResult::RobotsDisallowed => return None,
+            _ => None
+        };
+        Some(Self {
+            url: url.to_string(),
+            status,
+            error: result.error_kind(),
+            linked_from: report.get_linked_from().iter().map(ToString::to_string).collect()
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct StatusReplyV2 {
+    version: u8,
+    finished: bool,
+    verdict: Option<JobVerdictReply>,
+    timing: JobTiming,
+    broken: Option<Vec<BrokenLink>>,
+    results: HashMap<String, UrlResult>
+}
+
+impl From<StatusReplyMessage> for StatusReplyV2 {
+    fn from(reply: StatusReplyMessage) -> Self {
+        let finished = reply.is_finished();
+        let verdict = reply.get_verdict().map(JobVerdictReply::from);
+        let timing = JobTiming {
+            total: reply.get_total_summary().map(Percentiles::from),
+            ttfb: reply.get_ttfb_summary().map(Percentiles::from)
+        };
+        let results = reply.into_results();
+        let broken: Vec<BrokenLink> = results.iter()
+            .filter_map(|(url, report)| BrokenLink::of(url, report))
+            .collect();
+        Self {
+            version: 2,
+            finished,
+            verdict,
+            timing,
+            broken: (!broken.is_empty()).then_some(broken),
+            results: results
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.into()))
+                .collect()
+        }
+    }
+}
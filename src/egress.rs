@@ -0,0 +1,375 @@
+//! Egress policy
+//!
+//! Users pick the URLs we fetch, so without care they could make us reach
+//! services that only we can see : loopback, the private network, cloud
+//! metadata endpoints... Addresses are checked after resolution, in the
+//! resolver the HTTP clients use, so that a name resolving to something
+//! else the second time around does not get through.
+
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
+use reqwest::{
+    dns::{
+        Addrs,
+        Resolve,
+        Resolving
+    },
+    Url
+};
+use url::Host;
+
+use std::{
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        SocketAddr
+    },
+    sync::{
+        Arc,
+        OnceLock
+    }
+};
+
+use crate::{
+    dns::HostResolver,
+    extras::HostScope
+};
+
+/// Ranges that are never meant to be reached from the outside
+const INTERNAL_RANGES: [&str; 19] = [
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    // IPv4-compatible, which covers `::` and `::1`
+    "::/96",
+    // NAT64, whatever IPv4 address they lead to
+    "64:ff9b::/96",
+    // Local NAT64, where we cannot tell where the address is
+    "64:ff9b:1::/48",
+    // Discard
+    "100::/64",
+    // 6to4, just as well
+    "2002::/16",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8"
+];
+
+fn internal_ranges() -> &'static [IpNet] {
+    static RANGES: OnceLock<Vec<IpNet>> = OnceLock::new();
+    RANGES.get_or_init(|| INTERNAL_RANGES.iter()
+        .map(|net| net.parse().unwrap())
+        .collect())
+}
+
+/// The IPv4 address a NAT64 or 6to4 address is translated to
+fn relayed_ipv4(ip: IpAddr) -> Option<Ipv4Addr> {
+    let IpAddr::V6(ip) = ip else {
+        return None;
+    };
+    let octets = ip.octets();
+    match ip.segments() {
+        // The well-known prefix, where the address always ends it
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15])),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => None
+    }
+}
+
+/// Why a target was refused
+#[derive(Debug)]
+pub struct Blocked(String);
+
+impl std::fmt::Display for Blocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "blocked by the egress policy: {}", self.0)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// A host has no address left once pinned to a family or an address
+#[derive(Debug)]
+pub struct NoAddress(String);
+
+impl std::fmt::Display for NoAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no suitable address for {}", self.0)
+    }
+}
+
+impl std::error::Error for NoAddress {}
+
+/// Which addresses of a host connections may use
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AddressPin {
+    #[default]
+    Any,
+    V4,
+    V6,
+    /// Only this address for this host, anything for the others
+    Exact(String, IpAddr)
+}
+
+impl AddressPin {
+    fn allows(&self, host: &str, ip: IpAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::V4 => ip.is_ipv4(),
+            Self::V6 => ip.is_ipv6(),
+            Self::Exact(pinned, addr) => pinned != host || *addr == ip
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EgressPolicy {
+    block_internal: bool,
+    allow_nets: Vec<IpNet>,
+    deny_nets: Vec<IpNet>,
+    allow_hosts: HostScope,
+    deny_hosts: HostScope
+}
+
+impl Default for EgressPolicy {
+    fn default() -> Self {
+        Self {
+            block_internal: true,
+            allow_nets: Vec::new(),
+            deny_nets: Vec::new(),
+            allow_hosts: HostScope::default(),
+            deny_hosts: HostScope::default()
+        }
+    }
+}
+
+impl EgressPolicy {
+    pub fn new(
+        block_internal: bool,
+        allow_nets: Vec<IpNet>,
+        deny_nets: Vec<IpNet>,
+        allow_hosts: HostScope,
+        deny_hosts: HostScope
+    ) -> Self {
+        Self { block_internal, allow_nets, deny_nets, allow_hosts, deny_hosts }
+    }
+
+    /// Whether `host` was explicitly trusted, whatever it resolves to
+    fn trusts(&self, host: &str) -> bool {
+        !self.allow_hosts.is_empty() && self.allow_hosts.matches_host(host)
+    }
+
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), Blocked> {
+        // An IPv4 address can hide in an IPv6 one
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip
+        };
+        // Relays lead to it, so it has to be allowed too
+        if let Some(relayed) = relayed_ipv4(ip) {
+            self.check_ip(relayed.into())
+                .map_err(|Blocked(reason)| Blocked(format!("{reason}, relayed by {ip}")))?;
+        }
+        if self.deny_nets.iter().any(|net| net.contains(&ip)) {
+            return Err(Blocked(format!("{ip} is in a denied network")));
+        }
+        if self.allow_nets.iter().any(|net| net.contains(&ip)) {
+            return Ok(());
+        }
+        if self.block_internal && internal_ranges().iter().any(|net| net.contains(&ip)) {
+            return Err(Blocked(format!("{ip} is an internal address")));
+        }
+        Ok(())
+    }
+
+    /// Everything that can be decided before resolving the host of `url`
+    pub fn check_url(&self, url: &Url) -> Result<(), Blocked> {
+        let host = url.host_str()
+            .ok_or_else(|| Blocked(String::from("no host")))?;
+        if !self.deny_hosts.is_empty() && self.deny_hosts.matches_host(host) {
+            return Err(Blocked(format!("host {host} is denied")));
+        }
+        match url.host() {
+            Some(Host::Ipv4(ip)) => self.check_ip(ip.into()),
+            Some(Host::Ipv6(ip)) => self.check_ip(ip.into()),
+            _ => Ok(())
+        }
+    }
+
+    /// Only keep the addresses of `host` we are allowed to connect to.
+    /// Addresses a job pinned the host to are the choice of the job, not
+    /// of the host, so trusting the host does not cover them.
+    pub fn filter(&self, host: &str, addrs: Vec<SocketAddr>, overridden: bool) -> Result<Vec<SocketAddr>, Blocked> {
+        if self.trusts(host) && !overridden {
+            return Ok(addrs);
+        }
+        let mut last = None;
+        let allowed: Vec<SocketAddr> = addrs.into_iter()
+            .filter(|addr| match self.check_ip(addr.ip()) {
+                Ok(()) => true,
+                Err(e) => {
+                    last = Some(e);
+                    false
+                }
+            })
+            .collect();
+        match (allowed.is_empty(), last) {
+            (true, Some(blocked)) => Err(blocked),
+            _ => Ok(allowed)
+        }
+    }
+
+    /// Resolve `host` with `resolver`, keeping only the addresses we may connect to
+    pub async fn resolve(&self, resolver: &HostResolver, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        let addrs = resolver.lookup(host, port).await?;
+        self.filter(host, addrs, resolver.overrides(host))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::PermissionDenied, e))
+    }
+}
+
+/// The resolver of HTTP clients that must follow the policy
+#[derive(Debug, Clone)]
+pub struct PolicyResolver {
+    policy: Arc<EgressPolicy>,
+    resolver: Arc<HostResolver>,
+    pin: AddressPin
+}
+
+impl PolicyResolver {
+    pub const fn new(policy: Arc<EgressPolicy>, resolver: Arc<HostResolver>, pin: AddressPin) -> Self {
+        Self { policy, resolver, pin }
+    }
+
+    /// Addresses of `host` that the pin and the policy both allow
+    pub async fn lookup(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error + Send + Sync>> {
+        let addrs: Vec<SocketAddr> = self.resolver.lookup(host, port).await?
+            .into_iter()
+            .filter(|addr| self.pin.allows(host, addr.ip()))
+            .collect();
+        if addrs.is_empty() {
+            return Err(NoAddress(host.into()).into());
+        }
+        let overridden = self.resolver.overrides(host);
+        Ok(self.policy.filter(host, addrs, overridden)?)
+    }
+}
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            // The port is set by the connector afterwards
+            let addrs: Addrs = Box::new(resolver.lookup(name.as_str(), 0).await?.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn trusting(host: &str) -> EgressPolicy {
+        EgressPolicy::new(true, Vec::new(), Vec::new(), HostScope::new(&[host.into()]), HostScope::default())
+    }
+
+    fn addrs(ips: &[&str]) -> Vec<SocketAddr> {
+        ips.iter().map(|ip| SocketAddr::new(ip.parse().unwrap(), 80)).collect()
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn mapped_addresses_are_checked_as_ipv4() {
+        let policy = EgressPolicy::new(true, Vec::new(), Vec::new(), HostScope::default(), HostScope::default());
+        assert!(policy.check_ip(ip("::ffff:127.0.0.1")).is_err());
+        assert!(policy.check_ip(ip("::ffff:10.0.0.1")).is_err());
+        assert!(policy.check_ip(ip("::ffff:169.254.169.254")).is_err());
+        assert!(policy.check_ip(ip("::ffff:93.184.216.34")).is_ok());
+        assert!(policy.check_url(&Url::parse("http://[::ffff:127.0.0.1]/").unwrap()).is_err());
+    }
+
+    #[test]
+    fn mapped_addresses_follow_ipv4_networks() {
+        let policy = EgressPolicy::new(
+            true,
+            vec!["10.1.0.0/16".parse().unwrap()],
+            vec!["93.184.216.0/24".parse().unwrap()],
+            HostScope::default(),
+            HostScope::default()
+        );
+        assert!(policy.check_ip(ip("::ffff:10.1.2.3")).is_ok());
+        assert!(policy.check_ip(ip("::ffff:10.2.0.1")).is_err());
+        assert!(policy.check_ip(ip("::ffff:93.184.216.34")).is_err());
+    }
+
+    #[test]
+    fn reserved_ranges_are_internal() {
+        let policy = EgressPolicy::default();
+        for raw in ["192.0.0.8", "198.18.0.1", "198.19.255.255", "::7f00:1", "::1", "100::1", "64:ff9b:1::1"] {
+            assert!(policy.check_ip(ip(raw)).is_err(), "{raw}");
+        }
+        assert!(policy.check_ip(ip("198.20.0.1")).is_ok());
+    }
+
+    #[test]
+    fn relayed_addresses_are_internal() {
+        let policy = EgressPolicy::default();
+        for raw in ["64:ff9b::7f00:1", "64:ff9b::5db8:d822", "2002:7f00:1::", "2002:5db8:d822::1"] {
+            assert!(policy.check_ip(ip(raw)).is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn allowed_relays_still_check_their_ipv4_address() {
+        let policy = EgressPolicy::new(
+            true,
+            vec!["64:ff9b::/96".parse().unwrap(), "2002::/16".parse().unwrap()],
+            vec!["93.184.216.0/24".parse().unwrap()],
+            HostScope::default(),
+            HostScope::default()
+        );
+        assert!(policy.check_ip(ip("64:ff9b::808:808")).is_ok());
+        assert!(policy.check_ip(ip("2002:808:808::1")).is_ok());
+        for raw in ["64:ff9b::7f00:1", "64:ff9b::a00:1", "2002:7f00:1::", "2002:a9fe:a9fe::", "64:ff9b::5db8:d822"] {
+            assert!(policy.check_ip(ip(raw)).is_err(), "{raw}");
+        }
+        let reason = policy.check_ip(ip("2002:7f00:1::")).unwrap_err().to_string();
+        assert!(reason.contains("127.0.0.1") && reason.contains("2002:7f00:1::"), "{reason}");
+    }
+
+    #[test]
+    fn trusted_hosts_resolve_anywhere() {
+        let policy = trusting("intranet.example");
+        assert_eq!(policy.filter("intranet.example", addrs(&["10.0.0.1"]), false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn overrides_of_trusted_hosts_are_checked() {
+        let policy = trusting("intranet.example");
+        assert!(policy.filter("intranet.example", addrs(&["127.0.0.1"]), true).is_err());
+        assert!(policy.filter("intranet.example", addrs(&["169.254.169.254"]), true).is_err());
+        let kept = policy.filter("intranet.example", addrs(&["127.0.0.1", "93.184.216.34"]), true).unwrap();
+        assert_eq!(kept, addrs(&["93.184.216.34"]));
+    }
+
+    #[tokio::test]
+    async fn resolving_an_overridden_host_is_checked() {
+        let policy = trusting("intranet.example");
+        let overrides = HashMap::from([(String::from("intranet.example"), vec!["127.0.0.1".parse().unwrap()])]);
+        let resolver = HostResolver::default().with_overrides(&overrides);
+        assert!(policy.resolve(&resolver, "intranet.example", 80).await.is_err());
+    }
+}
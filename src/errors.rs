@@ -6,19 +6,40 @@ pub struct EmptyRequest;
 impl reject::Reject for EmptyRequest {}
 
 #[derive(Debug)]
-pub struct InvalidUrl {
-    url: String
+pub struct InvalidUrls {
+    // Every bad entry, with the reason it was refused
+    entries: Vec<(String, String)>
 }
-impl InvalidUrl {
-    pub const fn new(st: String) -> Self {
-        Self { url: st }
+impl InvalidUrls {
+    pub const fn new(entries: Vec<(String, String)>) -> Self {
+        Self { entries }
     }
 
-    pub fn get_url(&self) -> &str {
-        &self.url
+    pub fn get_entries(&self) -> &[(String, String)] {
+        &self.entries
     }
 }
-impl reject::Reject for InvalidUrl {}
+impl reject::Reject for InvalidUrls {}
+
+#[derive(Debug)]
+pub struct TooManyUrls {
+    count: usize,
+    max: usize
+}
+impl TooManyUrls {
+    pub const fn new(count: usize, max: usize) -> Self {
+        Self { count, max }
+    }
+
+    pub const fn get_count(&self) -> usize {
+        self.count
+    }
+
+    pub const fn get_max(&self) -> usize {
+        self.max
+    }
+}
+impl reject::Reject for TooManyUrls {}
 
 #[derive(Debug)]
 pub struct SyncError<E> {
@@ -43,3 +64,85 @@ impl<E: std::error::Error + Send + Sync + 'static> reject::Reject for SyncError<
 pub struct Unauthorized;
 
 impl reject::Reject for Unauthorized {}
+
+/// Authenticated, but not allowed to do that
+#[derive(Debug)]
+pub struct Forbidden {
+    reason: String
+}
+impl Forbidden {
+    pub const fn new(reason: String) -> Self {
+        Self { reason }
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+}
+impl reject::Reject for Forbidden {}
+
+#[derive(Debug)]
+pub struct InvalidExpectation {
+    url: String,
+    reason: String
+}
+impl InvalidExpectation {
+    pub const fn new(url: String, reason: String) -> Self {
+        Self { url, reason }
+    }
+
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+}
+impl reject::Reject for InvalidExpectation {}
+
+#[derive(Debug)]
+pub struct InvalidOption {
+    reason: String
+}
+impl InvalidOption {
+    pub const fn new(reason: String) -> Self {
+        Self { reason }
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+}
+impl reject::Reject for InvalidOption {}
+
+/// A submission body that cannot be read
+#[derive(Debug)]
+pub struct InvalidUpload {
+    reason: String
+}
+impl InvalidUpload {
+    pub const fn new(reason: String) -> Self {
+        Self { reason }
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+}
+impl reject::Reject for InvalidUpload {}
+
+#[derive(Debug)]
+pub struct UnsupportedMediaType {
+    content_type: String
+}
+impl UnsupportedMediaType {
+    pub const fn new(content_type: String) -> Self {
+        Self { content_type }
+    }
+
+    pub fn get_content_type(&self) -> &str {
+        &self.content_type
+    }
+}
+impl reject::Reject for UnsupportedMediaType {}
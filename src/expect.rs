@@ -0,0 +1,270 @@
+//! Expectations on fetch outcomes, and the verdicts they lead to
+
+use reqwest::Url;
+use warp::http::StatusCode;
+
+use std::{
+    str::FromStr,
+    time::Duration
+};
+
+use crate::{
+    messages::{
+        DownloadResult,
+        FetchReport
+    },
+    soft404::Soft404Report
+};
+
+/// Either an exact status code or a whole class (`2xx`, `4xx`, ...)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusMatcher {
+    Code(StatusCode),
+    Class(u16)
+}
+
+impl StatusMatcher {
+    pub fn matches(self, status: StatusCode) -> bool {
+        match self {
+            Self::Code(code) => code == status,
+            Self::Class(class) => status.as_u16() / 100 == class
+        }
+    }
+}
+
+impl TryFrom<u16> for StatusMatcher {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        StatusCode::from_u16(code)
+            .map(Self::Code)
+            .map_err(|_| format!("invalid status code {code}"))
+    }
+}
+
+impl FromStr for StatusMatcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        if let Some(class) = lower.strip_suffix("xx") {
+            return match class.parse::<u16>() {
+                Ok(class @ 1..=5) => Ok(Self::Class(class)),
+                _ => Err(format!("invalid status class \"{s}\""))
+            };
+        }
+        lower.parse::<u16>()
+            .map_err(|_| format!("invalid status \"{s}\""))
+            .and_then(Self::try_from)
+    }
+}
+
+impl std::fmt::Display for StatusMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "{}", code.as_u16()),
+            Self::Class(class) => write!(f, "{class}xx")
+        }
+    }
+}
+
+fn describe(set: &[StatusMatcher]) -> String {
+    set.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Expectations {
+    status: Vec<StatusMatcher>,
+    redirect_status: Vec<StatusMatcher>,
+    final_url: Option<Url>,
+    max_latency: Option<Duration>
+}
+
+impl Expectations {
+    pub fn new(
+        status: Vec<StatusMatcher>,
+        redirect_status: Vec<StatusMatcher>,
+        final_url: Option<Url>,
+        max_latency: Option<Duration>
+    ) -> Self {
+        Self { status, redirect_status, final_url, max_latency }
+    }
+
+    /// Check a report against every expectation, collecting
+    /// the reason of each failure
+    pub fn evaluate(&self, report: &FetchReport) -> Verdict {
+        let mut failures = Vec::new();
+
+        match report.get_result() {
+            // The server told us the page did not change since it last passed
+            DownloadResult::Fetched(_) if report.is_unchanged() => {},
+            // Nothing HTTP to look at: the host answered, or we
+            // chose not to look, which says nothing about the URL
+            DownloadResult::Resolved | DownloadResult::Connected | DownloadResult::RobotsDisallowed => {},
+            DownloadResult::Fetched(status) => {
+                if !self.status.is_empty() && !self.status.iter().any(|m| m.matches(status)) {
+                    failures.push(format!(
+                        "status {} is not one of {}",
+                        status.as_u16(), describe(&self.status)
+                    ));
+                }
+            },
+            error => failures.push(format!(
+                "fetch failed: {}",
+                error.error_kind().unwrap_or("unknown")
+            ))
+        }
+
+        for route in report.get_routes() {
+            let over = route.get_address().map_or_else(
+                || route.get_family().as_str().to_string(),
+                |address| format!("{} ({address})", route.get_family().as_str())
+            );
+            match route.get_result() {
+                // A host without addresses of that family is not broken
+                DownloadResult::DnsNotFound => {},
+                DownloadResult::Fetched(status) => {
+                    if !self.status.is_empty() && !self.status.iter().any(|m| m.matches(status)) {
+                        failures.push(format!(
+                            "status {} over {over} is not one of {}",
+                            status.as_u16(), describe(&self.status)
+                        ));
+                    }
+                },
+                error => failures.push(format!(
+                    "fetch over {over} failed: {}",
+                    error.error_kind().unwrap_or("unknown")
+                ))
+            }
+        }
+
+        if !self.redirect_status.is_empty() {
+            match report.get_redirects().first() {
+                None => failures.push(format!(
+                    "expected a redirect with status {}",
+                    describe(&self.redirect_status)
+                )),
+                Some(hop) if !self.redirect_status.iter().any(|m| m.matches(hop.get_status())) => {
+                    failures.push(format!(
+                        "redirect status {} is not one of {}",
+                        hop.get_status().as_u16(), describe(&self.redirect_status)
+                    ));
+                },
+                Some(_) => {}
+            }
+        }
+
+        if let Some(expected) = &self.final_url {
+            if report.get_final_url() != expected {
+                failures.push(format!(
+                    "final URL {} is not {}",
+                    report.get_final_url(), expected
+                ));
+            }
+        }
+
+        if let Some(checks) = report.get_checks() {
+            failures.extend(checks.get_failures().iter().cloned());
+        }
+
+        if report.get_soft_404().is_some_and(Soft404Report::is_likely) {
+            failures.push(String::from("looks like a soft 404"));
+        }
+
+        if let Some(tls) = report.get_tls().filter(|tls| tls.is_expiring()) {
+            failures.push(tls.days_left().map_or_else(
+                || String::from("certificate is about to expire"),
+                |days| format!("certificate expires in {days} days")
+            ));
+        }
+
+        if let Some(max) = self.max_latency {
+            let total = report.get_timings().get_total();
+            if total > max {
+                failures.push(format!(
+                    "took {}ms, more than {}ms",
+                    total.as_millis(), max.as_millis()
+                ));
+            }
+        }
+
+        Verdict { failures }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    failures: Vec<String>
+}
+
+impl Verdict {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn get_failures(&self) -> &[String] {
+        &self.failures
+    }
+}
+
+/// Outcome of a whole job, as far as we know
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobVerdict {
+    Pending,
+    Pass,
+    Fail
+}
+
+impl JobVerdict {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Pass => "pass",
+            Self::Fail => "fail"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(code: u16) -> StatusCode {
+        StatusCode::from_u16(code).unwrap()
+    }
+
+    #[test]
+    fn classes_match_their_whole_range() {
+        let success: StatusMatcher = "2xx".parse().unwrap();
+        assert!(success.matches(status(200)));
+        assert!(success.matches(status(299)));
+        assert!(!success.matches(status(199)));
+        assert!(!success.matches(status(300)));
+        assert_eq!("4XX".parse::<StatusMatcher>().unwrap(), StatusMatcher::Class(4));
+    }
+
+    #[test]
+    fn codes_match_only_themselves() {
+        let gone: StatusMatcher = "404".parse().unwrap();
+        assert!(gone.matches(status(404)));
+        assert!(!gone.matches(status(400)));
+        assert_eq!(StatusMatcher::try_from(301).unwrap(), StatusMatcher::Code(status(301)));
+    }
+
+    #[test]
+    fn invalid_statuses_are_refused() {
+        for raw in ["0xx", "6xx", "xx", "2x", "99", "1000", "ok"] {
+            assert!(raw.parse::<StatusMatcher>().is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn matchers_display_as_written() {
+        for raw in ["3xx", "418"] {
+            assert_eq!(raw.parse::<StatusMatcher>().unwrap().to_string(), raw);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Per-job request headers, cookies and credentials
+//!
+//! Everything here may be sensitive : values are wrapped in [`Secret`]
+//! so that they never end up in logs, and every item is scoped to a list
+//! of hosts so that a redirection to another site does not leak them.
+
+use reqwest::{
+    header::{
+        HeaderName,
+        COOKIE
+    },
+    RequestBuilder,
+    Url
+};
+use serde::Deserialize;
+
+/// A string that is never printed
+#[derive(Clone, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// Hosts something applies to. `example.com` only matches that host,
+/// `*.example.com` matches it and all of its subdomains. An empty scope
+/// matches no host, so that nothing is sent everywhere by mistake.
+#[derive(Debug, Clone, Default)]
+pub struct HostScope {
+    hosts: Vec<String>
+}
+
+impl HostScope {
+    pub fn new(hosts: &[String]) -> Self {
+        Self {
+            hosts: hosts.iter()
+                .map(|h| h.trim_end_matches('.').to_ascii_lowercase())
+                .collect()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    pub fn matches(&self, url: &Url) -> bool {
+        url.host_str().is_some_and(|host| self.matches_host(host))
+    }
+
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.hosts.iter().any(|pattern| pattern.strip_prefix("*.").map_or_else(
+            || *pattern == host,
+            |domain| host == domain || host.ends_with(&format!(".{domain}"))
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    Basic {
+        user: String,
+        password: Secret
+    },
+    Bearer(Secret)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestExtras {
+    headers: Vec<(HostScope, HeaderName, Secret)>,
+    cookies: Vec<(HostScope, String, Secret)>,
+    credentials: Vec<(HostScope, Credentials)>
+}
+
+impl RequestExtras {
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.cookies.is_empty() && self.credentials.is_empty()
+    }
+
+    pub fn add_header(&mut self, scope: HostScope, name: HeaderName, value: Secret) {
+        self.headers.push((scope, name, value));
+    }
+
+    pub fn add_cookie(&mut self, scope: HostScope, name: String, value: Secret) {
+        self.cookies.push((scope, name, value));
+    }
+
+    pub fn add_credentials(&mut self, scope: HostScope, credentials: Credentials) {
+        self.credentials.push((scope, credentials));
+    }
+
+    /// Add to a request everything that is meant for the host of `url`
+    pub fn apply(&self, mut builder: RequestBuilder, url: &Url) -> RequestBuilder {
+        for (scope, name, value) in &self.headers {
+            if scope.matches(url) {
+                builder = builder.header(name, value.expose());
+            }
+        }
+
+        let cookies: Vec<String> = self.cookies.iter()
+            .filter(|(scope, _, _)| scope.matches(url))
+            .map(|(_, name, value)| format!("{}={}", name, value.expose()))
+            .collect();
+        if !cookies.is_empty() {
+            builder = builder.header(COOKIE, cookies.join("; "));
+        }
+
+        // Only the first matching credentials are used, there
+        // is only one `Authorization` header
+        if let Some((_, credentials)) = self.credentials.iter().find(|(scope, _)| scope.matches(url)) {
+            builder = match credentials {
+                Credentials::Basic { user, password } => builder.basic_auth(user, Some(password.expose())),
+                Credentials::Bearer(token) => builder.bearer_auth(token.expose())
+            };
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::{
+        header::AUTHORIZATION,
+        Client
+    };
+
+    fn scope(hosts: &[&str]) -> HostScope {
+        HostScope::new(&hosts.iter().map(ToString::to_string).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn empty_scopes_match_no_host() {
+        assert!(!scope(&[]).matches_host("example.com"));
+        assert!(!scope(&[]).matches(&Url::parse("https://example.com/").unwrap()));
+    }
+
+    #[test]
+    fn wildcards_match_subdomains() {
+        let scope = scope(&["*.Example.com.", "other.test"]);
+        assert!(scope.matches_host("example.com"));
+        assert!(scope.matches_host("www.example.com."));
+        assert!(scope.matches_host("OTHER.test"));
+        assert!(!scope.matches_host("badexample.com"));
+        assert!(!scope.matches_host("www.other.test"));
+    }
+
+    #[test]
+    fn extras_only_go_to_their_hosts() {
+        let mut extras = RequestExtras::default();
+        extras.add_header(scope(&["a.test"]), HeaderName::from_static("x-token"), String::from("t").into());
+        extras.add_cookie(scope(&["a.test"]), String::from("session"), String::from("s").into());
+        extras.add_credentials(scope(&["a.test"]), Credentials::Bearer(String::from("b").into()));
+        let client = Client::new();
+        for (url, expected) in [("https://a.test/", true), ("https://b.test/", false)] {
+            let url = Url::parse(url).unwrap();
+            let request = extras.apply(client.get(url.clone()), &url).build().unwrap();
+            let headers = request.headers();
+            assert_eq!(headers.contains_key("x-token"), expected);
+            assert_eq!(headers.contains_key(COOKIE), expected);
+            assert_eq!(headers.contains_key(AUTHORIZATION), expected);
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! Single URL fetching logic
+
+use chrono::Utc;
+use openssl::error::ErrorStack;
+use reqwest::{
+    header::{
+        CONTENT_TYPE,
+        LOCATION
+    },
+    Response,
+    StatusCode,
+    Url
+};
+use tracing::debug;
+use url::Host;
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    io,
+    net::IpAddr,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::{
+    checks::{
+        ContentChecks,
+        MAX_CHECKED_BODY
+    },
+    client::HttpClient,
+    conditional::Validators,
+    connector::{
+        CertificateRejected,
+        ConnectInfo
+    },
+    dns::DnsError,
+    egress::{
+        AddressPin,
+        Blocked,
+        NoAddress
+    },
+    job::JobContext,
+    links,
+    messages::{
+        AddressProbe,
+        DownloadResult,
+        Extraction,
+        Family,
+        FetchReport,
+        RedirectHop,
+        RouteReport,
+        Timings
+    },
+    robots::RobotsRules,
+    sitemap,
+    soft404::{
+        Baseline,
+        PageSummary,
+        Soft404Detector,
+        Soft404Report
+    },
+    tls::TlsReport
+};
+
+/// Bodies are not read past this size, so that endless
+/// or huge responses do not keep a worker busy
+const MAX_BODY_SIZE: u64 = 256 * 1024 * 1024;
+/// Most times a job can ask to try again after a transient error
+pub const MAX_RETRIES: u32 = 5;
+/// How long to wait before trying again the first time,
+/// doubled every time after that
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Look for a cause we can name in one error of the source chain
+fn classify_cause(err: &(dyn Error + 'static)) -> Option<DownloadResult> {
+    if err.is::<Blocked>() {
+        return Some(DownloadResult::Blocked);
+    }
+    if err.is::<NoAddress>() {
+        return Some(DownloadResult::DnsNotFound);
+    }
+    if err.is::<CertificateRejected>() {
+        return Some(DownloadResult::CertificateInvalid);
+    }
+    if let Some(stack) = err.downcast_ref::<ErrorStack>() {
+        // Hostname mismatches end up there too
+        let invalid = stack.errors().iter()
+            .any(|e| e.reason() == Some("certificate verify failed"));
+        return Some(if invalid {
+            DownloadResult::CertificateInvalid
+        } else {
+            DownloadResult::TlsError
+        });
+    }
+    if err.downcast_ref::<hyper::Error>().is_some_and(hyper::Error::is_incomplete_message) {
+        return Some(DownloadResult::ConnectionReset);
+    }
+    err.downcast_ref::<io::Error>().and_then(classify_io)
+}
+
+/// What an error of the system means for a connection
+pub fn classify_io(err: &io::Error) -> Option<DownloadResult> {
+    match err.get_ref().and_then(|inner| inner.downcast_ref::<DnsError>()) {
+        Some(DnsError::NotFound(_)) => return Some(DownloadResult::DnsNotFound),
+        Some(DnsError::NoData(_)) => return Some(DownloadResult::DnsNoData),
+        Some(DnsError::Timeout(_)) => return Some(DownloadResult::DnsTimeout),
+        Some(DnsError::Failure(_)) => return Some(DownloadResult::ConnectError),
+        None => {}
+    }
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => Some(DownloadResult::ConnectionRefused),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => Some(DownloadResult::ConnectionReset),
+        _ => None
+    }
+}
+
+fn classify_error(err: &(dyn Error + 'static)) -> DownloadResult {
+    // What went wrong is usually buried in the errors of the
+    // connector, which reqwest only calls connection errors
+    let mut source = Some(err);
+    while let Some(inner) = source {
+        if let Some(result) = classify_cause(inner) {
+            return result;
+        }
+        source = inner.source();
+    }
+    let mut source = Some(err);
+    while let Some(inner) = source {
+        if let Some(err) = inner.downcast_ref::<reqwest::Error>() {
+            return classify_reqwest(err);
+        }
+        if let Some(err) = inner.downcast_ref::<hyper::Error>() {
+            return if err.is_connect() {
+                DownloadResult::ConnectError
+            } else {
+                DownloadResult::RequestError
+            };
+        }
+        source = inner.source();
+    }
+    DownloadResult::UnknownError
+}
+
+fn classify_reqwest(err: &reqwest::Error) -> DownloadResult {
+    err.status().map_or_else(||
+        if err.is_redirect() {
+            DownloadResult::RedirectError
+        } else if err.is_timeout() {
+            DownloadResult::TimeOutError
+        } else if err.is_request() {
+            DownloadResult::RequestError
+        } else if err.is_connect() {
+            DownloadResult::ConnectError
+        } else if err.is_decode() {
+            DownloadResult::DecodeError
+        } else {
+            DownloadResult::UnknownError
+        }
+    , DownloadResult::Fetched)
+}
+
+/// The certificates the handshake behind `err` was shown, when they are
+/// the reason it failed
+fn rejected_certificates(err: &(dyn Error + 'static)) -> Option<TlsReport> {
+    let mut source = Some(err);
+    while let Some(inner) = source {
+        if let Some(rejected) = inner.downcast_ref::<CertificateRejected>() {
+            return Some(rejected.get_report().clone());
+        }
+        source = inner.source();
+    }
+    None
+}
+
+fn disallowed(hops: Vec<RedirectHop>, url: Url) -> (FetchReport, Option<Vec<u8>>) {
+    (
+        FetchReport::new(DownloadResult::RobotsDisallowed, hops, url)
+            .with_message(String::from("disallowed by robots.txt")),
+        None
+    )
+}
+
+/// The robots.txt rules `url` falls under, unless the job ignores them
+async fn robots_rules(job: &JobContext, url: &Url) -> Option<Arc<RobotsRules>> {
+    let robots = job.get_robots()?;
+    Some(robots.rules(job.get_client(), job.get_policy(), url).await)
+}
+
+fn too_large(hops: Vec<RedirectHop>, url: Url) -> (FetchReport, Option<Vec<u8>>) {
+    (
+        FetchReport::new(DownloadResult::BodyTooLarge, hops, url)
+            .with_message(format!("body larger than {MAX_BODY_SIZE} bytes")),
+        None
+    )
+}
+
+/// Read the final response of a fetch, running the content checks on it.
+/// The body is handed back if it is no bigger than `keep_body`.
+async fn complete(
+    mut response: Response,
+    hops: Vec<RedirectHop>,
+    url: Url,
+    checks: Option<&ContentChecks>,
+    keep_body: Option<usize>,
+    conditional: bool
+) -> (FetchReport, Option<Vec<u8>>) {
+    let status = response.status();
+    let validators = Validators::from_headers(response.headers());
+    let content_type = response.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(String::from);
+    let content_length = response.content_length();
+    if content_length.is_some_and(|length| length > MAX_BODY_SIZE) {
+        return too_large(hops, url);
+    }
+
+    // Drain the body so that the total time covers the whole response,
+    // keeping it around only if something has to look at it
+    let mut body: Vec<u8> = Vec::new();
+    let mut overflow = false;
+    let mut read: u64 = 0;
+    let keep_body = keep_body.max(checks.map(|_| MAX_CHECKED_BODY));
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                read += chunk.len() as u64;
+                if read > MAX_BODY_SIZE {
+                    return too_large(hops, url);
+                }
+                if let (Some(limit), false) = (keep_body, overflow) {
+                    overflow = body.len() + chunk.len() > limit;
+                    if !overflow {
+                        body.extend_from_slice(&chunk);
+                    }
+                }
+            },
+            Ok(None) => break,
+            Err(err) => return (
+                FetchReport::new(classify_error(&err), hops, url)
+                    .with_message(err.to_string()),
+                None
+            )
+        }
+    }
+    let body = (keep_body.is_some() && !overflow).then_some(body);
+
+    let mut report = FetchReport::new(DownloadResult::Fetched(status), hops, url)
+        .with_content(content_type.clone(), content_length)
+        .with_validators(validators);
+    // Nothing to check, the content is what it was last time
+    if conditional && status == StatusCode::NOT_MODIFIED {
+        return (report.with_unchanged(), None);
+    }
+    if let Some(checks) = checks {
+        let checked = body.as_deref().filter(|body| body.len() <= MAX_CHECKED_BODY);
+        report = report.with_checks(checks.evaluate(content_type.as_deref(), checked));
+    }
+    (report, body)
+}
+
+/// What we learn from the first response of a fetch
+#[derive(Debug, Default)]
+struct FirstResponse {
+    ttfb: Option<Duration>,
+    address: Option<IpAddr>,
+    // The connection it came over, and whether the fetch opened it
+    connection: Option<ConnectInfo>,
+    opened: bool,
+    // The certificates that made the connection fail
+    rejected: Option<TlsReport>
+}
+
+impl FirstResponse {
+    /// The connection the fetch opened, if it did not reuse one
+    fn opened(&self) -> Option<&ConnectInfo> {
+        self.connection.as_ref().filter(|_| self.opened)
+    }
+}
+
+/// Fetch `url` with `client`, following redirections by hand
+/// so that every hop can be recorded
+async fn fetch_once(
+    job: &JobContext,
+    client: &HttpClient,
+    url: Url,
+    checks: Option<&ContentChecks>,
+    keep_body: Option<usize>,
+    validators: Option<&Validators>,
+    first: &mut FirstResponse
+) -> (FetchReport, Option<Vec<u8>>) {
+    let options = job.get_options();
+    let mut current = url;
+    let mut hops: Vec<RedirectHop> = Vec::new();
+    let mut visited: HashSet<Url> = HashSet::new();
+    visited.insert(current.clone());
+
+    loop {
+        // Redirections are checked too, the resolver only
+        // sees names and not addresses written in URLs
+        if let Err(blocked) = job.get_policy().check_url(&current) {
+            return (
+                FetchReport::new(DownloadResult::Blocked, hops, current)
+                    .with_message(blocked.to_string()),
+                None
+            );
+        }
+        // The URL itself was allowed before we got here
+        if !hops.is_empty() && robots_rules(job, &current).await.is_some_and(|rules| !rules.allows(&current)) {
+            return disallowed(hops, current);
+        }
+        let start = Instant::now();
+        let mut request = options.get_extras()
+            .apply(client.get(current.clone()), &current);
+        // Validators were given for the URL itself, not where it leads
+        let conditional = hops.is_empty() && validators.is_some();
+        if let (Some(validators), true) = (validators, conditional) {
+            request = validators.apply(request);
+        }
+        let response = match client.send(request).await {
+            Ok(rs) => rs,
+            Err(err) => {
+                if hops.is_empty() {
+                    first.rejected = rejected_certificates(&err);
+                }
+                return (
+                    FetchReport::new(classify_error(&err), hops, current)
+                        .with_message(err.to_string()),
+                    None
+                );
+            }
+        };
+        // Only the first hop tells us about the URL that was submitted
+        if first.ttfb.is_none() {
+            let connection = response.extensions().get::<ConnectInfo>().cloned();
+            // The request only goes out once the connection is ready
+            let sent = connection.as_ref().map_or(start, |connection| connection.get_ready().max(start));
+            first.ttfb = Some(sent.elapsed());
+            first.address = response.remote_addr().map(|remote| remote.ip())
+                .or_else(|| connection.as_ref().map(ConnectInfo::get_address));
+            // A connection opened earlier, by another request, was reused
+            first.opened = connection.as_ref()
+                .is_some_and(|connection| connection.get_ready() >= start && connection.claim());
+            first.connection = connection;
+        }
+        let status = response.status();
+        if !status.is_redirection() {
+            return complete(response, hops, current, checks, keep_body, conditional).await;
+        }
+        // Some 3xx (304, 300, ...) legitimately come without a location
+        let Some(location) = response.headers().get(LOCATION) else {
+            return complete(response, hops, current, checks, keep_body, conditional).await;
+        };
+        let Some(next) = location.to_str().ok()
+            .and_then(|loc| current.join(loc).ok()) else {
+            let message = format!("Invalid redirect location {location:?}");
+            return (
+                FetchReport::new(DownloadResult::RedirectError, hops, current)
+                    .with_message(message),
+                None
+            );
+        };
+        hops.push(RedirectHop::new(status, next.clone()));
+
+        if !options.follows_redirects() {
+            return complete(response, hops, current, checks, keep_body, conditional).await;
+        }
+        if !visited.insert(next.clone()) {
+            let message = format!("Redirect loop back to {next}");
+            return (
+                FetchReport::new(DownloadResult::RedirectLoop, hops, current)
+                    .with_message(message),
+                None
+            );
+        }
+        if hops.len() > options.get_max_redirects() {
+            let message = format!("More than {} redirects", options.get_max_redirects());
+            return (
+                FetchReport::new(DownloadResult::TooManyRedirects, hops, current)
+                    .with_message(message),
+                None
+            );
+        }
+        current = next;
+    }
+}
+
+/// Compare a page against what its host answers for missing pages
+async fn detect_soft_404(
+    job: &JobContext,
+    detector: &Soft404Detector,
+    url: &Url,
+    body: &[u8]
+) -> Option<Soft404Report> {
+    let baseline = detector.baseline(url, |probe_url| async move {
+        debug!("Fetching soft 404 baseline {}", probe_url);
+        let mut first = FirstResponse::default();
+        match fetch_once(job, job.get_client(), probe_url, None, Some(MAX_CHECKED_BODY), None, &mut first).await {
+            (report, Some(body)) => match report.get_result() {
+                DownloadResult::Fetched(status) => Some(Baseline::new(status, PageSummary::new(&body))),
+                _ => None
+            },
+            (_, None) => None
+        }
+    }).await?;
+    Some(Soft404Report::compare(&baseline, &PageSummary::new(body)))
+}
+
+/// Fetch `url` again over every family, or every address, of its host.
+/// Addresses written in the URL leave nothing to choose from.
+async fn fetch_routes(url: &Url, job: &JobContext) -> Vec<RouteReport> {
+    let Some(Host::Domain(host)) = url.host() else {
+        return Vec::new();
+    };
+    let pins: Vec<(Family, Option<IpAddr>, AddressPin)> = match job.get_options().get_address_probe() {
+        AddressProbe::Off => return Vec::new(),
+        AddressProbe::Families => vec![
+            (Family::V4, None, AddressPin::V4),
+            (Family::V6, None, AddressPin::V6)
+        ],
+        // If the host does not resolve, the fetch itself already says so
+        AddressProbe::Addresses => job.resolve(url).await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|addr| (Family::of(addr.ip()), Some(addr.ip()), AddressPin::Exact(host.into(), addr.ip())))
+            .collect()
+    };
+    let mut routes = Vec::new();
+    for (family, address, pin) in pins {
+        let start = Instant::now();
+        let mut first = FirstResponse::default();
+        let (result, message) = match job.pinned_client(pin) {
+            Ok(client) => {
+                let (report, _) = fetch_once(job, &client, url.clone(), None, None, None, &mut first).await;
+                (report.get_result(), report.get_message().map(String::from))
+            },
+            Err(e) => (DownloadResult::UnknownError, Some(e.to_string()))
+        };
+        let address = address.or(first.address);
+        routes.push(RouteReport::new(family, address, result, message, start.elapsed()));
+    }
+    routes
+}
+
+/// Where a fetch stands between two of its attempts
+#[derive(Debug, Default)]
+pub struct Attempt {
+    // Attempts made so far
+    made: u32,
+    // Whether the next one has its turn at the origin booked
+    booked: bool
+}
+
+impl Attempt {
+    /// Count one more attempt, and tell its number
+    pub fn begin(&mut self) -> u32 {
+        self.made += 1;
+        self.made
+    }
+
+    /// Put the next attempt off, for longer after every failure
+    pub fn retry(self) -> Outcome {
+        let backoff = RETRY_BACKOFF * 2u32.pow(self.made.saturating_sub(1).min(MAX_RETRIES));
+        Outcome::Later(Instant::now() + backoff, self)
+    }
+}
+
+/// What became of a fetch, or of a check
+#[derive(Debug)]
+pub enum Outcome {
+    Done(Box<FetchReport>),
+    /// The fetch goes on with its next attempt, no sooner than then
+    Later(Instant, Attempt)
+}
+
+/// Make the next attempt at fetching `url`. The fetch is put off when its
+/// origin asks for some delay between requests, or when the attempt fails
+/// and the job allows to try again. What `extraction` finds in the page
+/// comes back with the report.
+pub async fn fetch(
+    url: Url,
+    job: &JobContext,
+    checks: Option<&ContentChecks>,
+    validators: Option<&Validators>,
+    extraction: Extraction,
+    mut attempt: Attempt
+) -> Outcome {
+    let options = job.get_options();
+    let rules = robots_rules(job, &url).await;
+    if rules.as_ref().is_some_and(|rules| !rules.allows(&url)) {
+        return Outcome::Done(Box::new(disallowed(Vec::new(), url).0));
+    }
+    if let (Some(robots), Some(delay), false) = (job.get_robots(), rules.as_ref().and_then(|rules| rules.get_delay()), attempt.booked) {
+        let turn = robots.book_turn(&url, delay).await;
+        if turn > Instant::now() {
+            attempt.booked = true;
+            return Outcome::Later(turn, attempt);
+        }
+    }
+    attempt.booked = false;
+    let number = attempt.begin();
+    // Sitemaps are allowed to be bigger than the pages we look into
+    let keep_body = match extraction {
+        Extraction::Sitemap => Some(sitemap::MAX_SITEMAP_SIZE),
+        Extraction::Links => Some(MAX_CHECKED_BODY),
+        Extraction::Off => job.get_soft_404().map(|_| MAX_CHECKED_BODY)
+    };
+    let fetched_at = Utc::now();
+    let mut first = FirstResponse::default();
+    let start = Instant::now();
+    let (mut report, body) = fetch_once(
+        job, job.get_client(), url.clone(), checks, keep_body, validators, &mut first
+    ).await;
+    let total = start.elapsed();
+    if number <= options.get_retries() && report.get_result().is_transient() {
+        debug!("Attempt {} on {} failed, retrying", number, url);
+        return attempt.retry();
+    }
+    report.set_attempt(number, fetched_at);
+    let opened = first.opened();
+    report.set_timings(Timings::new(
+        opened.and_then(ConnectInfo::get_dns),
+        opened.map(ConnectInfo::get_connect),
+        opened.and_then(ConnectInfo::get_tls),
+        first.ttfb,
+        total
+    ));
+    // Through a proxy, the other end is the proxy
+    if !job.is_proxied() {
+        report.set_address(first.address);
+    }
+    if let Some(connection) = &first.connection {
+        report = report.with_addresses(connection.get_addresses().to_vec());
+    }
+    let certificates = first.connection.as_ref()
+        .and_then(ConnectInfo::get_certificates)
+        .cloned()
+        .or(first.rejected);
+    if let Some(mut certificates) = certificates {
+        if let Some(days) = options.get_cert_warning_days() {
+            certificates.set_warning(days);
+        }
+        report.set_tls(certificates);
+    }
+    Outcome::Done(Box::new(conclude(report, body, &url, job, extraction).await))
+}
+
+/// Add to the report of the last attempt on `url` what
+/// else the job wants to know about it
+async fn conclude(
+    mut report: FetchReport,
+    body: Option<Vec<u8>>,
+    url: &Url,
+    job: &JobContext,
+    extraction: Extraction
+) -> FetchReport {
+    report.set_routes(fetch_routes(url, job).await);
+
+    // Only pages that claim to be fine can be soft 404s
+    let success = matches!(report.get_result(), DownloadResult::Fetched(status) if status.is_success());
+    let Some(body) = body.filter(|_| success) else {
+        // Then the only way to lose the body is for it to be too big
+        if success && extraction == Extraction::Sitemap {
+            report = report.with_message(format!("invalid sitemap: larger than {} bytes", sitemap::MAX_SITEMAP_SIZE));
+        }
+        return report;
+    };
+    let is_html = report.get_content_type()
+        .is_some_and(|ct| ct.to_ascii_lowercase().starts_with("text/html"));
+    match extraction {
+        Extraction::Links if is_html => {
+            let links = links::extract(&body, report.get_final_url());
+            report.set_links(links);
+        },
+        Extraction::Sitemap => match sitemap::parse(&body, report.get_final_url()) {
+            Ok(links) => {
+                report = report.with_message(format!("{} entries listed", links.len()));
+                report.set_links(links);
+            },
+            Err(e) => report = report.with_message(format!("invalid sitemap: {e}"))
+        },
+        _ => {}
+    }
+    if let Some(detector) = job.get_soft_404() {
+        let final_url = report.get_final_url().clone();
+        if let Some(soft_404) = detect_soft_404(job, detector, &final_url, &body).await {
+            report.set_soft_404(soft_404);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let mut attempt = Attempt::default();
+        for expected in [500, 1000, 2000, 4000] {
+            attempt.begin();
+            let start = Instant::now();
+            let Outcome::Later(when, next) = attempt.retry() else {
+                panic!("a retry is for later");
+            };
+            let backoff = when - start;
+            assert!(backoff >= Duration::from_millis(expected) && backoff < Duration::from_millis(expected + 100));
+            attempt = next;
+        }
+    }
+
+    #[test]
+    fn system_errors_are_classified_by_kind() {
+        let classify = |err| classify_io(&err);
+        assert!(matches!(classify(io::Error::other(DnsError::NotFound("a.example".into()))), Some(DownloadResult::DnsNotFound)));
+        assert!(matches!(classify(io::Error::other(DnsError::NoData("a.example".into()))), Some(DownloadResult::DnsNoData)));
+        assert!(matches!(classify(io::Error::other(DnsError::Timeout("a.example".into()))), Some(DownloadResult::DnsTimeout)));
+        assert!(matches!(classify(io::Error::other(DnsError::Failure("bad".into()))), Some(DownloadResult::ConnectError)));
+        assert!(matches!(classify(io::ErrorKind::ConnectionRefused.into()), Some(DownloadResult::ConnectionRefused)));
+        assert!(matches!(classify(io::ErrorKind::BrokenPipe.into()), Some(DownloadResult::ConnectionReset)));
+        assert!(classify(io::Error::other("failed to lookup address information")).is_none());
+    }
+}
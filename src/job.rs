@@ -0,0 +1,115 @@
+//! Per-job state shared with the workers
+
+use reqwest::Url;
+use url::Host;
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc
+};
+
+use crate::{
+    client::{
+        BuildError,
+        ClientSettings,
+        HttpClient
+    },
+    dns::HostResolver,
+    egress::{
+        AddressPin,
+        EgressPolicy
+    },
+    messages::FetchOptions,
+    robots::RobotsCache,
+    soft404::Soft404Detector
+};
+
+#[derive(Debug)]
+pub struct JobContext {
+    options: FetchOptions,
+    client: HttpClient,
+    proxied: bool,
+    policy: Arc<EgressPolicy>,
+    resolver: Arc<HostResolver>,
+    // To build clients pinned to some addresses
+    settings: ClientSettings,
+    soft_404: Option<Soft404Detector>,
+    robots: Option<Arc<RobotsCache>>
+}
+
+impl JobContext {
+    /// Set up a job, reusing `default_client` unless the job needs
+    /// a client of its own, and the robots.txt rules of other jobs
+    /// unless what it fetches is its own business
+    pub fn new(
+        options: FetchOptions,
+        settings: &ClientSettings,
+        default_client: &HttpClient,
+        shared_robots: &Arc<RobotsCache>
+    ) -> Result<Self, BuildError> {
+        let client = if options.uses_default_client() {
+            default_client.clone()
+        } else {
+            settings.build(&options)?
+        };
+        let proxied = settings.effective_proxy(options.get_proxy()).is_some();
+        let soft_404 = options.detects_soft_404()
+            .then(Soft404Detector::default);
+        let policy = Arc::clone(settings.get_policy());
+        let resolver = settings.resolver_for(options.get_overrides());
+        let robots_cache = options.follows_robots().then(|| if options.is_shareable() {
+            Arc::clone(shared_robots)
+        } else {
+            Arc::new(RobotsCache::new(settings.get_user_agent()))
+        });
+        let settings = settings.clone();
+        Ok(Self { options, client, proxied, policy, resolver, settings, soft_404, robots: robots_cache })
+    }
+
+    pub const fn get_client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    /// A client of the job that only connects where `pin` allows
+    pub fn pinned_client(&self, pin: AddressPin) -> Result<HttpClient, BuildError> {
+        self.settings.build_pinned(&self.options, pin)
+    }
+
+    /// Whether requests go through a proxy, in which case we
+    /// cannot say much about the connection to the target
+    pub const fn is_proxied(&self) -> bool {
+        self.proxied
+    }
+
+    pub fn get_policy(&self) -> &EgressPolicy {
+        &self.policy
+    }
+
+    /// Addresses of the host of `url` the job may connect to
+    pub async fn resolve(&self, url: &Url) -> io::Result<Vec<SocketAddr>> {
+        let port = url.port_or_known_default()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No port for URL"))?;
+        self.policy.check_url(url)
+            .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
+        match url.host() {
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "No host in URL")),
+            Some(Host::Ipv4(ip)) => Ok(vec![SocketAddr::new(ip.into(), port)]),
+            Some(Host::Ipv6(ip)) => Ok(vec![SocketAddr::new(ip.into(), port)]),
+            Some(Host::Domain(domain)) => self.policy.resolve(&self.resolver, domain, port).await
+        }
+    }
+
+    pub const fn get_options(&self) -> &FetchOptions {
+        &self.options
+    }
+
+    pub const fn get_soft_404(&self) -> Option<&Soft404Detector> {
+        self.soft_404.as_ref()
+    }
+
+    /// The robots.txt rules met so far, unless the job ignores them
+    pub fn get_robots(&self) -> Option<&RobotsCache> {
+        self.robots.as_deref()
+    }
+}
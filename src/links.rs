@@ -0,0 +1,335 @@
+//! Link extraction
+//!
+//! Pages submitted as seeds are checked like any other URL, and then
+//! searched for the links, images, scripts and stylesheets they refer
+//! to. There is no need for a full HTML parser to find those: the tags
+//! we care about are simple enough to be spotted on their own, once
+//! comments and inline scripts are out of the way.
+//!
+//! A crawl goes further, and searches the pages it finds as well, as
+//! long as they are close enough to a seed and part of the site. Links
+//! that leave the site are checked, but not followed.
+
+use regex::Regex;
+use reqwest::Url;
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet
+    },
+    sync::OnceLock
+};
+
+/// How many links away from a seed a crawl goes by default
+pub const DEFAULT_MAX_DEPTH: usize = 2;
+/// How many pages a crawl searches by default, seeds included
+pub const DEFAULT_MAX_PAGES: usize = 100;
+
+/// A URL found on a page or in a sitemap
+#[derive(Debug, Clone)]
+pub struct Link {
+    url: Url,
+    lastmod: Option<String>,
+    sitemap: bool
+}
+
+impl Link {
+    pub const fn new(url: Url) -> Self {
+        Self { url, lastmod: None, sitemap: false }
+    }
+
+    /// When the page last changed, according to a sitemap
+    pub fn with_lastmod(mut self, lastmod: Option<String>) -> Self {
+        self.lastmod = lastmod.filter(|lastmod| !lastmod.is_empty());
+        self
+    }
+
+    /// The URL is a sitemap of its own
+    pub const fn with_sitemap(mut self, sitemap: bool) -> Self {
+        self.sitemap = sitemap;
+        self
+    }
+
+    pub const fn is_sitemap(&self) -> bool {
+        self.sitemap
+    }
+
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn explode(self) -> (Url, Option<String>) {
+        (self.url, self.lastmod)
+    }
+}
+
+fn noise_regex() -> &'static Regex {
+    static NOISE: OnceLock<Regex> = OnceLock::new();
+    // Whatever is inside a script or a comment is not markup
+    NOISE.get_or_init(|| Regex::new(r"(?is)<!--.*?-->|(<script\b[^>]*>).*?</script>").unwrap())
+}
+
+fn tag_regex() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"(?is)<(a|img|script|link|base)\b([^>]*)>").unwrap())
+}
+
+fn attribute_regex() -> &'static Regex {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    ATTRIBUTE.get_or_init(|| Regex::new(
+        r#"(?is)(?:^|\s)(href|src)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#
+    ).unwrap())
+}
+
+/// The value of `name` in the attributes of a tag
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    attribute_regex()
+        .captures_iter(attributes)
+        .find(|c| c[1].eq_ignore_ascii_case(name))
+        .and_then(|c| c.get(2).or_else(|| c.get(3)).or_else(|| c.get(4)))
+        .map(|value| value.as_str())
+}
+
+/// Undo the few entities found in URLs
+pub fn unescape(value: &str) -> String {
+    value.trim()
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The tags of `body` that refer to other URLs, along with the URL each
+/// refers to as written, in the order they appear
+pub fn references(body: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(body);
+    let text = noise_regex().replace_all(&text, "$1");
+    tag_regex()
+        .captures_iter(&text)
+        .filter_map(|tag| {
+            let name = tag[1].to_ascii_lowercase();
+            let wanted = match name.as_str() {
+                "img" | "script" => "src",
+                _ => "href"
+            };
+            attribute(&tag[2], wanted).map(|value| (name, unescape(value)))
+        })
+        .collect()
+}
+
+/// Every URL `body` refers to, resolved against `page`, in the order they
+/// first appear. Links within the page itself and links that cannot be
+/// fetched, like `mailto:` ones, are left out.
+pub fn extract(body: &[u8], page: &Url) -> Vec<Link> {
+    let mut own = page.clone();
+    own.set_fragment(None);
+    let references = references(body);
+    // Only the first `<base>` counts, wherever the links are
+    let base = references.iter()
+        .find(|(name, _)| name == "base")
+        .and_then(|(_, value)| page.join(value).ok())
+        .unwrap_or_else(|| page.clone());
+    let mut seen = HashSet::new();
+    let mut links = Vec::new();
+    for (name, value) in references {
+        if name == "base" {
+            continue;
+        }
+        let Ok(mut url) = base.join(&value) else {
+            continue;
+        };
+        if !matches!(url.scheme(), "http" | "https") {
+            continue;
+        }
+        url.set_fragment(None);
+        if url != own && seen.insert(url.clone()) {
+            links.push(Link::new(url));
+        }
+    }
+    links
+}
+
+/// Where a crawl is allowed to go
+#[derive(Debug, Clone)]
+enum Scope {
+    /// A host and its subdomains
+    Host(String),
+    /// Every URL of the same origin whose path is this one's,
+    /// or goes below it
+    Prefix(Url)
+}
+
+impl Scope {
+    fn parse(entry: &str) -> Result<Self, String> {
+        if entry.contains("://") {
+            let url = Url::parse(entry)
+                .map_err(|e| format!("invalid crawl prefix \"{entry}\" ({e})"))?;
+            if url.host_str().is_none_or(str::is_empty) {
+                return Err(format!("no host in crawl prefix \"{entry}\""));
+            }
+            return Ok(Self::Prefix(url));
+        }
+        let host = entry.trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() {
+            return Err(String::from("empty host in crawl scope"));
+        }
+        Ok(Self::Host(host))
+    }
+
+    fn covers(&self, url: &Url) -> bool {
+        match self {
+            Self::Host(host) => url.host_str().is_some_and(|h| {
+                h == host || h.strip_suffix(host.as_str()).is_some_and(|sub| sub.ends_with('.'))
+            }),
+            // `/docs` covers `/docs/intro` but not `/docsify`
+            Self::Prefix(prefix) => url.origin() == prefix.origin() && {
+                let base = prefix.path().trim_end_matches('/');
+                url.path().strip_prefix(base).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            }
+        }
+    }
+}
+
+/// The limits of a crawl, as asked by a job
+#[derive(Debug, Clone)]
+pub struct CrawlLimits {
+    max_depth: usize,
+    max_pages: usize,
+    scope: Vec<Scope>
+}
+
+impl CrawlLimits {
+    /// `scope` lists hosts and URL prefixes. If there are none,
+    /// the crawl stays on the hosts of the seeds.
+    pub fn new(max_depth: usize, max_pages: usize, scope: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            max_depth,
+            max_pages,
+            scope: scope.iter()
+                .map(|entry| Scope::parse(entry))
+                .collect::<Result<_, _>>()?
+        })
+    }
+}
+
+/// How far a running crawl went
+#[derive(Debug)]
+pub struct Crawl {
+    limits: CrawlLimits,
+    depths: HashMap<Url, usize>
+}
+
+impl Crawl {
+    pub fn new<'a>(limits: &CrawlLimits, seeds: impl Iterator<Item = &'a Url>) -> Self {
+        let mut limits = limits.clone();
+        let depths: HashMap<Url, usize> = seeds.map(|seed| (seed.clone(), 0)).collect();
+        if limits.scope.is_empty() {
+            limits.scope = depths.keys()
+                .filter_map(Url::host_str)
+                .map(|host| Scope::Host(host.into()))
+                .collect();
+        }
+        Self { limits, depths }
+    }
+
+    /// Whether `url` is part of the site being crawled
+    pub fn covers(&self, url: &Url) -> bool {
+        self.limits.scope.iter().any(|scope| scope.covers(url))
+    }
+
+    pub fn is_seed(&self, url: &Url) -> bool {
+        self.depths.get(url) == Some(&0)
+    }
+
+    /// Whether `link`, found on `page`, is to be searched for links too
+    pub fn follow(&mut self, page: &Url, link: &Url) -> bool {
+        let Some(depth) = self.depths.get(page).map(|depth| depth + 1) else {
+            return false;
+        };
+        if depth > self.limits.max_depth
+            || self.depths.len() >= self.limits.max_pages
+            || self.depths.contains_key(link)
+            || !self.covers(link) {
+            return false;
+        }
+        self.depths.insert(link.clone(), depth);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(raw: &str) -> Url {
+        Url::parse(raw).unwrap()
+    }
+
+    fn crawl(scope: &[&str]) -> Crawl {
+        let scope: Vec<String> = scope.iter().map(ToString::to_string).collect();
+        let limits = CrawlLimits::new(DEFAULT_MAX_DEPTH, DEFAULT_MAX_PAGES, &scope).unwrap();
+        Crawl::new(&limits, [url("https://site.test/")].iter())
+    }
+
+    fn extracted(body: &str, page: &str) -> Vec<String> {
+        extract(body.as_bytes(), &url(page)).into_iter()
+            .map(|link| link.explode().0.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn links_resolve_against_the_page() {
+        let body = r#"<a href="intro">x</a> <img src='/logo.png'> <a href=../up?a=1&amp;b=2>"#;
+        assert_eq!(extracted(body, "https://site.test/docs/index.html"), [
+            "https://site.test/docs/intro",
+            "https://site.test/logo.png",
+            "https://site.test/up?a=1&b=2"
+        ]);
+    }
+
+    #[test]
+    fn links_resolve_against_the_first_base() {
+        let body = r#"<a href="before"><base href="/v2/"><a href="after"><base href="https://other.test/">"#;
+        assert_eq!(extracted(body, "https://site.test/docs/page"), [
+            "https://site.test/v2/before",
+            "https://site.test/v2/after"
+        ]);
+        let body = r#"<base href="https://cdn.test/assets/"><script src="app.js"></script>"#;
+        assert_eq!(extracted(body, "https://site.test/"), ["https://cdn.test/assets/app.js"]);
+    }
+
+    #[test]
+    fn own_fragments_other_schemes_and_repeats_are_left_out() {
+        let body = r##"<a href="#top"> <a href="mailto:a@site.test"> <a href="javascript:void(0)">
+            <a href="other#one"> <a href="other#two"> <!-- <a href="hidden"> -->"##;
+        assert_eq!(extracted(body, "https://site.test/page#here"), ["https://site.test/other"]);
+    }
+
+    #[test]
+    fn prefixes_end_at_a_path_segment() {
+        for prefix in ["https://site.test/docs", "https://site.test/docs/"] {
+            let crawl = crawl(&[prefix]);
+            assert!(crawl.covers(&url("https://site.test/docs/intro")));
+            assert!(crawl.covers(&url("https://site.test/docs/?page=2")));
+            assert!(!crawl.covers(&url("https://site.test/docsify")));
+            assert!(!crawl.covers(&url("https://site.test/")));
+        }
+        assert!(crawl(&["https://site.test/docs"]).covers(&url("https://site.test/docs")));
+    }
+
+    #[test]
+    fn prefixes_stay_on_their_origin() {
+        let crawl = crawl(&["https://site.test/"]);
+        assert!(crawl.covers(&url("https://site.test/anything")));
+        assert!(crawl.covers(&url("https://SITE.test:443/anything")));
+        assert!(!crawl.covers(&url("http://site.test/anything")));
+        assert!(!crawl.covers(&url("https://site.test:8443/anything")));
+        assert!(!crawl.covers(&url("https://site.test.evil/anything")));
+    }
+
+    #[test]
+    fn hosts_cover_their_subdomains() {
+        let crawl = crawl(&["site.test."]);
+        assert!(crawl.covers(&url("https://www.site.test/")));
+        assert!(!crawl.covers(&url("https://othersite.test/")));
+    }
+}
@@ -35,6 +35,7 @@
 
 use clap::{
 	Arg,
+	ArgMatches,
 	Command
 };
 use tracing::{
@@ -50,14 +51,41 @@ use tokio::{
 	}
 };
 
-use std::io;
+use std::{
+	io,
+	net::{
+		IpAddr,
+		SocketAddr
+	},
+	sync::Arc
+};
 
 mod api;
 mod auth;
+mod cache;
+mod checks;
+mod client;
+mod conditional;
+mod connector;
+mod dns;
+mod documents;
 mod dto;
+mod egress;
 mod errors;
+mod expect;
+mod extras;
+mod fetch;
+mod job;
+mod links;
 mod manager;
 mod messages;
+mod netcheck;
+mod robots;
+mod sitemap;
+mod soft404;
+mod tls;
+mod upload;
+mod validate;
 
 fn create_subscriber() -> Result<(), Box<dyn std::error::Error>> {
 	let subscriber = tracing_subscriber::fmt()
@@ -73,6 +101,207 @@ fn create_subscriber() -> Result<(), Box<dyn std::error::Error>> {
 	Ok(())
 }
 
+/// What the server may connect to
+fn egress_args() -> [Arg<'static>; 5] {
+	[
+		Arg::new("allow-internal")
+			.long("allow-internal")
+			.env("HEXICHOR_ALLOW_INTERNAL")
+			.help("Let users fetch private, loopback and link-local addresses"),
+		Arg::new("allow-net")
+			.long("allow-net")
+			.env("HEXICHOR_ALLOW_NETS")
+			.value_name("cidr")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.use_value_delimiter(true)
+			.help("Networks that can always be fetched"),
+		Arg::new("deny-net")
+			.long("deny-net")
+			.env("HEXICHOR_DENY_NETS")
+			.value_name("cidr")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.use_value_delimiter(true)
+			.help("Networks that can never be fetched"),
+		Arg::new("allow-host")
+			.long("allow-host")
+			.env("HEXICHOR_ALLOW_HOSTS")
+			.value_name("host")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.use_value_delimiter(true)
+			.help("Hosts that can be fetched whatever they resolve to (\"*.example.com\" for a whole domain)"),
+		Arg::new("deny-host")
+			.long("deny-host")
+			.env("HEXICHOR_DENY_HOSTS")
+			.value_name("host")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.use_value_delimiter(true)
+			.help("Hosts that can never be fetched (\"*.example.com\" for a whole domain)")
+	]
+}
+
+/// The `run` subcommand and its many settings
+fn run_command() -> Command<'static> {
+	Command::new("run")
+		.about("Run the server")
+		.arg(Arg::new("bind")
+			.short('b')
+			.long("bind")
+			.env("HEXICHOR_HOST")
+			.default_value("0.0.0.0")
+			.takes_value(true)
+			.help("Address to bind to"))
+		.arg(Arg::new("port")
+			.short('p')
+			.long("port")
+			.env("HEXICHOR_PORT")
+			.value_name("port")
+			.takes_value(true)
+			.help("Port to bind to"))
+		.arg(Arg::new("proxy")
+			.long("proxy")
+			.env("HEXICHOR_PROXY")
+			.value_name("url")
+			.takes_value(true)
+			.help("Proxy to fetch URLs through (http, https, socks5 or socks5h)"))
+		.arg(Arg::new("no-proxy")
+			.long("no-proxy")
+			.env("HEXICHOR_NO_PROXY")
+			.value_name("hosts")
+			.takes_value(true)
+			.requires("proxy")
+			.help("Comma-separated hosts, domains and networks that are not proxied"))
+		.args(egress_args())
+		.arg(Arg::new("schemes")
+			.long("schemes")
+			.env("HEXICHOR_SCHEMES")
+			.value_name("scheme")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.use_value_delimiter(true)
+			.default_value("http,https")
+			.help("URL schemes that can be submitted"))
+		.arg(Arg::new("max-url-length")
+			.long("max-url-length")
+			.env("HEXICHOR_MAX_URL_LENGTH")
+			.value_name("bytes")
+			.takes_value(true)
+			.default_value("2048")
+			.help("Longest URL that can be submitted"))
+		.arg(Arg::new("max-urls")
+			.long("max-urls")
+			.env("HEXICHOR_MAX_URLS")
+			.value_name("count")
+			.takes_value(true)
+			.default_value("1000")
+			.help("Most URLs a single job can hold"))
+		.arg(Arg::new("cache-ttl")
+			.long("cache-ttl")
+			.env("HEXICHOR_CACHE_TTL")
+			.value_name("seconds")
+			.takes_value(true)
+			.help("Share results between jobs for that long (no sharing by default)"))
+		.arg(Arg::new("dns-server")
+			.long("dns-server")
+			.env("HEXICHOR_DNS_SERVERS")
+			.value_name("address")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.use_value_delimiter(true)
+			.help("DNS servers to ask instead of the system resolver, in order (port 53 by default)"))
+		.arg(Arg::new("ca-bundle")
+			.long("ca-bundle")
+			.env("HEXICHOR_CA_BUNDLE")
+			.value_name("path")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.help("PEM file of certificate authorities to trust on top of the system ones"))
+		.arg(Arg::new("client-identity")
+			.long("client-identity")
+			.value_name("name=cert.pem:key.pem")
+			.takes_value(true)
+			.multiple_occurrences(true)
+			.help("Client certificate and PKCS#8 key jobs can present to targets under that name"))
+		.arg(Arg::new("user-agent")
+			.long("user-agent")
+			.env("HEXICHOR_USER_AGENT")
+			.value_name("agent")
+			.takes_value(true)
+			.help("User agent to send, and to follow the robots.txt rules of"))
+}
+
+/// Read what submitted URLs should look like from the command line
+fn url_policy(cmd: &ArgMatches) -> io::Result<validate::UrlPolicy> {
+	let number = |name: &str| cmd.value_of(name)
+		.unwrap().parse()
+		.map_err(|e: std::num::ParseIntError| io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("invalid {name}: {e}")
+		));
+	Ok(validate::UrlPolicy::new(
+		cmd.values_of("schemes").unwrap().map(String::from).collect(),
+		number("max-url-length")?,
+		number("max-urls")?
+	))
+}
+
+/// Read the settings of the HTTP clients from the command line
+fn client_settings(cmd: &ArgMatches) -> io::Result<client::ClientSettings> {
+	let proxy = cmd.value_of("proxy")
+		.map(|url| client::ProxySettings::new(
+			String::from(url).into(),
+			cmd.value_of("no-proxy").map(String::from)
+		));
+	if let Some(proxy) = &proxy {
+		proxy.validate()
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+	}
+	let nets = |name: &str| cmd.values_of(name)
+		.map_or_else(|| Ok(Vec::new()), |values| values.map(str::parse).collect())
+		.map_err(|e: ipnet::AddrParseError| io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("invalid {name}: {e}")
+		));
+	let hosts = |name: &str| extras::HostScope::new(&cmd.values_of(name)
+		.map_or_else(Vec::new, |values| values.map(String::from).collect::<Vec<String>>()));
+	let policy = egress::EgressPolicy::new(
+		!cmd.is_present("allow-internal"),
+		nets("allow-net")?,
+		nets("deny-net")?,
+		hosts("allow-host"),
+		hosts("deny-host")
+	);
+	let mut settings = client::ClientSettings::new(proxy, policy);
+	let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+	let dns_servers = cmd.values_of("dns-server").into_iter().flatten()
+		.map(|server| server.parse::<SocketAddr>()
+			.or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+			.map_err(|e| invalid(format!("invalid dns-server \"{server}\": {e}"))))
+		.collect::<io::Result<Vec<SocketAddr>>>()?;
+	if !dns_servers.is_empty() {
+		settings.set_dns_servers(dns_servers);
+	}
+	for path in cmd.values_of("ca-bundle").into_iter().flatten() {
+		settings.add_ca_bundle(&std::fs::read(path)?)
+			.map_err(|e| invalid(format!("{path}: {e}")))?;
+	}
+	for spec in cmd.values_of("client-identity").into_iter().flatten() {
+		let (name, cert, key) = spec.split_once('=')
+			.and_then(|(name, paths)| paths.split_once(':').map(|(cert, key)| (name, cert, key)))
+			.filter(|(name, _, _)| !name.is_empty())
+			.ok_or_else(|| invalid(format!("invalid client identity \"{spec}\", expected name=cert.pem:key.pem")))?;
+		settings.add_identity(name.into(), &std::fs::read(cert)?, &std::fs::read(key)?)
+			.map_err(invalid)?;
+	}
+	if let Some(user_agent) = cmd.value_of("user-agent") {
+		settings.set_user_agent(user_agent.into());
+	}
+	Ok(settings)
+}
+
 #[tokio::main]
 #[tracing::instrument(level="info")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -91,23 +320,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 					.help("Optionally provide password in command line (note that this is insecure as it could be visible from your command line history)")
 					.required(false)
 					.takes_value(true)))
-		.subcommand(
-			Command::new("run")
-				.about("Run the server")
-				.arg(Arg::new("bind")
-					.short('b')
-					.long("bind")
-					.env("HEXICHOR_HOST")
-					.default_value("0.0.0.0")
-					.takes_value(true)
-					.help("Address to bind to"))
-				.arg(Arg::new("port")
-					.short('p')
-					.long("port")
-					.env("HEXICHOR_PORT")
-					.value_name("port")
-					.takes_value(true)
-					.help("Port to bind to")))
+		.subcommand(run_command())
 		.get_matches();
 
 	match args.subcommand() {
@@ -122,7 +335,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 						e.to_string()
 					)
 				})?;
-			run_server(host_bind, port_bind).await
+			let cache_ttl = cmd.value_of("cache-ttl")
+				.map(str::parse)
+				.transpose()
+				.map_err(|e: std::num::ParseIntError| io::Error::new(
+					io::ErrorKind::InvalidInput,
+					format!("invalid cache-ttl: {e}")
+				))?
+				.filter(|&secs| secs > 0)
+				.map(std::time::Duration::from_secs);
+			let settings = client_settings(cmd)?;
+			let url_policy = url_policy(cmd)?.with_egress(Arc::clone(settings.get_policy()));
+			run_server(host_bind, port_bind, settings, url_policy, cache_ttl).await
 		},
 		Some(("mkpass", _cmd)) => {
 			Ok(())
@@ -140,7 +364,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 
-async fn run_server(host_bind: String, port_bind: u16) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_server(
+	host_bind: String,
+	port_bind: u16,
+	settings: client::ClientSettings,
+	url_policy: validate::UrlPolicy,
+	cache_ttl: Option<std::time::Duration>
+) -> Result<(), Box<dyn std::error::Error>> {
 	create_subscriber()?;
 	debug!("Logger initialized");
 
@@ -150,11 +380,15 @@ async fn run_server(host_bind: String, port_bind: u16) -> Result<(), Box<dyn std
 	let (shut_tx, shut_rx) = broadcast::channel(1);
 
 	// Manager thread
+	let policy = url_policy.clone();
 	let manager_handle = tokio::spawn(async move {
 		manager::manager(
 			req_rx,
 			poll_rx,
-			shut_rx
+			shut_rx,
+			settings,
+			policy,
+			cache_ttl
 		).await
 	});
 
@@ -169,6 +403,7 @@ async fn run_server(host_bind: String, port_bind: u16) -> Result<(), Box<dyn std
 				(&host_bind, port_bind),
 				req_tx,
 				poll_tx,
+				url_policy,
 				shut_rx
 				).await.is_err() {
 			error!("Signaling shutdown");
@@ -2,6 +2,7 @@
 
 use reqwest::Url;
 use tracing::{
+    debug,
     error,
     info,
     warn
@@ -11,95 +12,281 @@ use tokio::{
         broadcast,
         mpsc
     },
-    task::JoinHandle
+    task::JoinHandle,
+    time::sleep_until
 };
 use uuid::Uuid;
 
-use std::collections::{
-    hash_map::Entry,
-    HashMap
+use std::{
+    collections::{
+        hash_map::Entry,
+        HashMap,
+        HashSet
+    },
+    sync::Arc,
+    time::{
+        Duration,
+        Instant
+    }
 };
 
-use crate::messages::{
-    DownloadResult,
-    RequestMessage,
+use crate::{
+    cache::{
+        CacheKey,
+        ResultCache
+    },
+    client::{
+        BuildError,
+        ClientSettings,
+        HttpClient
+    },
+    conditional::Validators,
+    expect::{
+        Expectations,
+        JobVerdict
+    },
+    fetch::{
+        fetch,
+        Attempt,
+        Outcome
+    },
+    job::JobContext,
+    links::{
+        Crawl,
+        Link
+    },
+    messages::{
+        AddressProbe,
+        CheckMode,
+        Extraction,
+        FetchOptions,
+        FetchReport,
+        LatencySummary,
+        RequestMessage,
     SingleUrlDownload,
     SingleUrlResult,
-    StatusRequestMessage,
-    StatusReplyMessage
+        StatusRequestMessage,
+        StatusReplyMessage,
+        Target,
+        VerdictSummary
+    },
+    netcheck,
+    robots::RobotsCache,
+    validate::{
+        normalize,
+        UrlPolicy
+    }
 };
 
 #[derive(Debug)]
 pub struct Request {
-    urls: HashMap<Url, Option<DownloadResult>>,
-    remaining: usize
+    urls: HashMap<Url, Option<FetchReport>>,
+    remaining: usize,
+    // Kept for the whole life of the job, results are
+    // handed out as soon as they are polled
+    totals: Vec<Duration>,
+    ttfbs: Vec<Duration>,
+    expectations: HashMap<Url, Expectations>,
+    // Whether any URL gets a verdict at all
+    judged: bool,
+    passed: usize,
+    failed: usize,
+    // Where to store results for other jobs, if they can be shared
+    cache_keys: HashMap<Url, CacheKey>,
+    shareable: bool,
+    // Every URL gets a verdict when certificates are watched
+    cert_warning: bool,
+    // Every URL the job ever had, so that links are only added once
+    seen: HashSet<Url>,
+    // Pages that link to every URL, for jobs with seeds
+    linked_from: HashMap<Url, Vec<Url>>,
+    // When pages last changed, for those listed in sitemaps
+    lastmods: HashMap<Url, String>,
+    // Labels given along with URLs, handed back with their results
+    labels: HashMap<Url, HashMap<String, String>>,
+    job: Option<Arc<JobContext>>,
+    crawl: Option<Crawl>
 }
 
 impl Request {
-    fn update(&mut self, url: Url, res: DownloadResult) {
+    fn add(&mut self, target: Target) {
+        let (url, expect, checks, labels) = target.explode();
+        if !self.seen.insert(url.clone()) {
+            return;
+        }
+        if !labels.is_empty() {
+            self.labels.insert(url.clone(), labels);
+        }
+        self.judged |= expect.is_some() || checks.is_some();
+        if let Some(expect) = expect {
+            self.expectations.insert(url.clone(), expect);
+        }
+        self.urls.insert(url, None);
+        self.remaining += 1;
+    }
+
+    fn update(&mut self, url: Url, mut res: FetchReport) {
         match self.urls.entry(url) {
             Entry::Vacant(_) => { /* do nothing */ }
+            // Already there, nothing to count twice
+            Entry::Occupied(e) if e.get().is_some() => { /* do nothing */ }
             Entry::Occupied(mut e) => {
                 self.remaining -= 1;
+                // Cached results took their time for another job
+                if !res.is_cached() {
+                    self.totals.push(res.get_timings().get_total());
+                    if let Some(ttfb) = res.get_timings().get_ttfb() {
+                        self.ttfbs.push(ttfb);
+                    }
+                }
+                let expect = self.expectations.get(e.key());
+                if expect.is_some() || res.get_checks().is_some() || self.cert_warning {
+                    let verdict = expect.map_or_else(
+                        || Expectations::default().evaluate(&res),
+                        |expect| expect.evaluate(&res)
+                    );
+                    if verdict.passed() {
+                        self.passed += 1;
+                    } else {
+                        self.failed += 1;
+                    }
+                    res.set_verdict(verdict);
+                }
                 e.insert(Some(res));
             }
         }
     }
 
+    fn verdict(&self) -> Option<VerdictSummary> {
+        if !self.judged {
+            return None;
+        }
+        // A single failure is enough to know how the job ends
+        let verdict = if self.failed > 0 {
+            JobVerdict::Fail
+        } else if self.remaining == 0 {
+            JobVerdict::Pass
+        } else {
+            JobVerdict::Pending
+        };
+        Some(VerdictSummary::new(verdict, self.passed, self.failed))
+    }
+
     fn fetch_done(&mut self) -> StatusReplyMessage {
-        let mut done: HashMap<Url, DownloadResult> = HashMap::new();
-        for entry in &self.urls {
-            if let Some(val) = entry.1 {
-                done.insert(entry.0.clone(), *val);
+        let done_keys: Vec<Url> = self.urls.iter()
+            .filter(|(_, v)| v.is_some())
+            .map(|(k, _)| k.clone())
+            .collect();
+        let mut done: HashMap<Url, FetchReport> = HashMap::new();
+        for key in done_keys {
+            if let Some(Some(mut val)) = self.urls.remove(&key) {
+                if let Some(pages) = self.linked_from.get(&key) {
+                    val.set_linked_from(pages.clone());
+                }
+                val.set_lastmod(self.lastmods.get(&key).cloned());
+                if let Some(labels) = self.labels.get(&key) {
+                    val.set_labels(labels.clone());
+                }
+                done.insert(key, val);
             }
         }
-        for key in done.keys() {
-            self.urls.remove(key);
-        }
-        
-        StatusReplyMessage::new(self.remaining == 0, done)
+
+        StatusReplyMessage::new(
+            self.remaining == 0,
+            done,
+            LatencySummary::from_samples(&self.totals),
+            LatencySummary::from_samples(&self.ttfbs),
+            self.verdict()
+        )
     }
 }
 
-impl From<Vec<Url>> for Request {
-    fn from(vc: Vec<Url>) -> Self {
-        let len = vc.len();
-        Self {
-            urls: vc.into_iter()
-                .map(|k| (k, None))
-                .collect::<HashMap<Url, Option<DownloadResult>>>(),
-            remaining: len
+impl From<Vec<Target>> for Request {
+    fn from(vc: Vec<Target>) -> Self {
+        let mut request = Self {
+            urls: HashMap::new(),
+            remaining: 0,
+            totals: Vec::new(),
+            ttfbs: Vec::new(),
+            expectations: HashMap::new(),
+            judged: false,
+            passed: 0,
+            failed: 0,
+            cache_keys: HashMap::new(),
+            shareable: false,
+            cert_warning: false,
+            seen: HashSet::new(),
+            linked_from: HashMap::new(),
+            lastmods: HashMap::new(),
+            labels: HashMap::new(),
+            job: None,
+            crawl: None
+        };
+        for target in vc {
+            request.add(target);
         }
+        request
     }
 }
 
+/// How long the validators of a URL are kept for the next
+/// conditional request to it
+const VALIDATORS_TTL: Duration = Duration::from_hours(24);
+
 #[derive(Debug)]
 pub struct Manager {
     reqs: HashMap<Uuid, Request>,
     workers: Vec<JoinHandle<()>>,
-    dispatch_tx: async_channel::Sender<SingleUrlDownload>
+    dispatch_tx: async_channel::Sender<SingleUrlDownload>,
+    cache: Option<ResultCache>,
+    // Last known validators of every URL, for conditional requests,
+    // and when they were stored
+    validators: HashMap<Url, (Instant, Validators)>,
+    settings: ClientSettings,
+    // Shared by every job that sticks to the server settings
+    client: HttpClient,
+    // And so are the robots.txt rules of the sites they fetch
+    robots: Arc<RobotsCache>,
+    // Links found on seeds are held to the same rules as submitted URLs
+    url_policy: UrlPolicy
 }
 
 impl Manager {
-    pub fn new(ret_tx: &mpsc::Sender<SingleUrlResult>, wcount: usize) -> Self {
+    pub fn new(
+        ret_tx: &mpsc::Sender<SingleUrlResult>,
+        requeue_tx: &mpsc::Sender<SingleUrlDownload>,
+        wcount: usize,
+        settings: ClientSettings,
+        cache: Option<ResultCache>,
+        url_policy: UrlPolicy
+    ) -> Result<Self, BuildError> {
+        let client = settings.build(&FetchOptions::default())?;
+        let robots = Arc::new(RobotsCache::new(settings.get_user_agent()));
         // Channels
         let (sg_tx, sg_rx) = async_channel::unbounded();
         let mut workers = Vec::new();
         for i in 0..wcount {
             let new_rx = sg_rx.clone();
             let new_tx = ret_tx.clone();
+            let requeue_tx = requeue_tx.clone();
             let handler = tokio::spawn(async move {
-                if worker(i, new_rx, new_tx).await.is_err() {
+                if worker(i, new_rx, new_tx, requeue_tx).await.is_err() {
                     error!("Worker {} terminated", i);
                 }
             });
             workers.push(handler);
         }
-        Self {
+        Ok(Self {
             reqs: HashMap::new(),
             workers,
-            dispatch_tx: sg_tx
-        }
+            dispatch_tx: sg_tx,
+            cache,
+            validators: HashMap::new(),
+            settings,
+            client,
+            robots,
+            url_policy
+        })
     }
 
     fn collect(&mut self, uuid: Uuid) -> Option<StatusReplyMessage> {
@@ -116,14 +303,152 @@ impl Manager {
         }
     }
 
-    fn set_result(&mut self, uuid: Uuid, url: Url, res: DownloadResult) {
+    async fn set_result(&mut self, uuid: Uuid, url: Url, mut res: FetchReport) {
+        let mut links = res.take_links();
         // Find entry in the dictionary
         if let Some(inner) = self.reqs.get_mut(&uuid) {
-            inner.update(url, res);
+            // A page that redirects away from the site is not part of it
+            if inner.crawl.as_ref().is_some_and(|crawl| !crawl.is_seed(&url) && !crawl.covers(res.get_final_url())) {
+                links.clear();
+            }
+            if let (Some(cache), Some(key)) = (&mut self.cache, inner.cache_keys.remove(&url)) {
+                cache.insert(key, &res);
+            }
+            // What was fetched with credentials is nobody else's business.
+            // Validators are sent along with the URL itself, so the ones
+            // of a page it redirects to are of no use.
+            if let (Some(validators), true, true) = (res.get_validators(), inner.shareable, res.get_final_url() == &url) {
+                self.validators.insert(url.clone(), (Instant::now(), validators.clone()));
+            }
+            inner.update(url.clone(), res);
+        }
+        if !links.is_empty() {
+            self.add_links(uuid, &url, links).await;
+        }
+    }
+
+    /// Add the links found on `page` to its job. They are judged like
+    /// the page itself, and searched for links if the crawl goes that far.
+    /// Sitemaps listed in a sitemap are read as well.
+    async fn add_links(&mut self, uuid: Uuid, page: &Url, links: Vec<Link>) {
+        let Some(inner) = self.reqs.get_mut(&uuid) else {
+            return;
+        };
+        let Some(job) = inner.job.clone() else {
+            return;
+        };
+        let expect = inner.expectations.get(page).cloned();
+        let mut targets = Vec::new();
+        let mut fresh = HashSet::new();
+        let mut dropped = 0;
+        for link in links {
+            let is_sitemap = link.is_sitemap();
+            let (link, lastmod) = link.explode();
+            let link = match self.url_policy.validate(link.as_str()) {
+                Ok(url) => normalize(url, job.get_options().sorts_query()),
+                Err(reason) => {
+                    debug!("Ignoring link to {} found on {}: {}", link, page, reason);
+                    continue;
+                }
+            };
+            inner.linked_from.entry(link.clone()).or_default().push(page.clone());
+            if let Some(lastmod) = lastmod {
+                inner.lastmods.insert(link.clone(), lastmod);
+            }
+            if inner.seen.contains(&link) || fresh.contains(&link) {
+                continue;
+            }
+            if inner.seen.len() + fresh.len() >= self.url_policy.get_max_urls() {
+                dropped += 1;
+                continue;
+            }
+            fresh.insert(link.clone());
+            let extraction = if is_sitemap {
+                Extraction::Sitemap
+            } else if inner.crawl.as_mut().is_some_and(|crawl| crawl.follow(page, &link)) {
+                Extraction::Links
+            } else {
+                Extraction::Off
+            };
+            let target = Target::new(link, CheckMode::Http, expect.clone(), None)
+                .with_sitemap(is_sitemap);
+            targets.push((target, extraction));
+        }
+        if dropped > 0 {
+            warn!("Job {} is full, ignored {} links found on {}", uuid, dropped, page);
+        }
+        for (target, extraction) in targets {
+            let url = target.get_url().clone();
+            let cache_key = self.cache_key(&job, &target, extraction);
+            let hit = cache_key.as_ref().and_then(|cache_key| self.lookup(&job, cache_key));
+            if hit.is_none() {
+                self.send(uuid, &job, &target, extraction).await;
+            }
+            let Some(inner) = self.reqs.get_mut(&uuid) else {
+                return;
+            };
+            inner.add(target);
+            match (hit, cache_key) {
+                (Some(report), _) => inner.update(url, report),
+                (None, Some(cache_key)) => {
+                    inner.cache_keys.insert(url, cache_key);
+                },
+                (None, None) => {}
+            }
         }
     }
 
-    pub async fn register(&mut self, urls: Vec<Url>) -> Uuid {
+    /// Where the result for `target` is stored, if it can be shared. Pages
+    /// searched for links come with them, which are not worth keeping.
+    fn cache_key(&self, job: &JobContext, target: &Target, extraction: Extraction) -> Option<CacheKey> {
+        let shared = self.cache.is_some() && job.get_options().is_shareable();
+        (shared && extraction == Extraction::Off).then(|| CacheKey::new(
+            target.get_url(),
+            target.get_mode(),
+            job.get_options(),
+            target.get_checks().map(AsRef::as_ref)
+        ))
+    }
+
+    fn lookup(&self, job: &JobContext, cache_key: &CacheKey) -> Option<FetchReport> {
+        self.cache.as_ref()
+            .filter(|_| job.get_options().uses_cache())
+            .and_then(|cache| cache.get(cache_key))
+    }
+
+    /// Hand `target` over to the workers
+    async fn send(&self, key: Uuid, job: &Arc<JobContext>, target: &Target, extraction: Extraction) {
+        let url = target.get_url();
+        let mode = target.get_mode();
+        let validators = (job.get_options().is_conditional() && mode == CheckMode::Http)
+            .then(|| self.validators.get(url))
+            .flatten()
+            .filter(|(stored, _)| stored.elapsed() < VALIDATORS_TTL)
+            .map(|(_, validators)| validators.clone());
+        self.dispatch_tx.send((
+            key,
+            url.clone(),
+            mode,
+            Arc::clone(job),
+            target.get_checks().cloned(),
+            validators,
+            extraction,
+            Attempt::default()
+        )).await.unwrap();
+    }
+
+    pub async fn register(&mut self, urls: Vec<Target>, options: FetchOptions) -> Result<Uuid, String> {
+        self.settings.check_proxy(options.get_proxy()).await?;
+        self.settings.check_tls(options.get_tls())?;
+        let job = Arc::new(JobContext::new(options, &self.settings, &self.client, &self.robots)
+            .map_err(|e| format!("unable to set up the HTTP client: {e}"))?);
+        // The proxy picks the addresses, we cannot
+        if job.is_proxied() && job.get_options().get_address_probe() != AddressProbe::Off {
+            return Err(String::from("address families cannot be probed through a proxy, use \"proxy\": \"direct\""));
+        }
+        if job.is_proxied() && !job.get_options().get_overrides().is_empty() {
+            return Err(String::from("hosts cannot be overridden through a proxy, use \"proxy\": \"direct\""));
+        }
         // Generate UUID
         let mut key = Uuid::new_v4();
         while self.reqs.contains_key(&key) {
@@ -133,11 +458,59 @@ impl Manager {
               urls.len(),
               key
         );
-        for url in &urls {
-            self.dispatch_tx.send((key, url.clone())).await.unwrap();
+        let seeds = job.get_options().has_seeds();
+        let mut cache_keys = HashMap::new();
+        let mut hits = Vec::new();
+        for target in &urls {
+            let url = target.get_url();
+            let extraction = if target.is_sitemap() {
+                Extraction::Sitemap
+            } else if seeds && target.get_mode() == CheckMode::Http {
+                Extraction::Links
+            } else {
+                Extraction::Off
+            };
+            if let Some(cache_key) = self.cache_key(&job, target, extraction) {
+                if let Some(report) = self.lookup(&job, &cache_key) {
+                    hits.push((url.clone(), report));
+                    continue;
+                }
+                cache_keys.insert(url.clone(), cache_key);
+            }
+            self.send(key, &job, target, extraction).await;
+        }
+        if !hits.is_empty() {
+            info!("Using {} cached results for UUID={}", hits.len(), key);
+        }
+        let urls_from_sitemaps = urls.iter().any(Target::is_sitemap);
+        let crawl = job.get_options().get_crawl().map(|limits| Crawl::new(
+            limits,
+            urls.iter()
+                .filter(|target| target.get_mode() == CheckMode::Http)
+                .map(Target::get_url)
+        ));
+        let mut request = Request::from(urls);
+        request.crawl = crawl;
+        request.cache_keys = cache_keys;
+        request.shareable = job.get_options().is_shareable();
+        // Certificates about to expire fail their URL
+        request.cert_warning = job.get_options().get_cert_warning_days().is_some();
+        request.judged |= request.cert_warning;
+        // Links found later are fetched the same way
+        request.job = (seeds || urls_from_sitemaps).then_some(job);
+        for (url, report) in hits {
+            request.update(url, report);
         }
-        self.reqs.insert(key, Request::from(urls));
-        key
+        self.reqs.insert(key, request);
+        Ok(key)
+    }
+
+    /// Forget results and validators that are not fresh anymore
+    fn purge(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache.purge();
+        }
+        self.validators.retain(|_, (stored, _)| stored.elapsed() < VALIDATORS_TTL);
     }
 
     async fn shutdown(self) {
@@ -148,22 +521,34 @@ impl Manager {
     }
 }
 
-#[tracing::instrument(level="info", skip(req_rx,poll_rx, shutdown_rx))]
+#[tracing::instrument(level="info", skip(req_rx,poll_rx, shutdown_rx, settings, url_policy))]
 pub async fn manager(
     mut req_rx: mpsc::Receiver<RequestMessage>,
     mut poll_rx: mpsc::Receiver<StatusRequestMessage>,
-    mut shutdown_rx: broadcast::Receiver<()>
+    mut shutdown_rx: broadcast::Receiver<()>,
+    settings: ClientSettings,
+    url_policy: UrlPolicy,
+    cache_ttl: Option<Duration>
 ) -> Result<(), Box<dyn std::error::Error + Send>> {
     let (ret_tx, mut ret_rx) = mpsc::channel(128);
-    let mut data = Manager::new(&ret_tx, 5);
+    // Fetches put off come back through there once their time has come
+    let (requeue_tx, mut requeue_rx) = mpsc::channel(128);
+    let cache = cache_ttl.map(ResultCache::new);
+    // Stale results are dropped once in a while
+    let mut purge = tokio::time::interval(cache_ttl.unwrap_or(Duration::from_hours(1)));
+    let mut data = Manager::new(&ret_tx, &requeue_tx, 5, settings, cache, url_policy)
+        .map_err(|e| {
+            error!("Unable to build the HTTP client: {}", e);
+            Box::new(e) as Box<dyn std::error::Error + Send>
+        })?;
     loop {
         tokio::select! {
             Some(reqmsg) = req_rx.recv() => {
                 //info!("I got a reqmsg: {:?}", reqmsg);
                 // Explode the request
-                let (urls, ret_tx) = reqmsg.explode();
+                let (urls, options, ret_tx) = reqmsg.explode();
                 // Register the request and respond
-                if ret_tx.send(data.register(urls).await).is_err() {
+                if ret_tx.send(data.register(urls, options).await).is_err() {
                     error!("Unable to send back addition result");
                 }
             }
@@ -187,7 +572,13 @@ pub async fn manager(
             }
             Some(result) = ret_rx.recv() => {
                 let (uuid, url, res) = result;
-                data.set_result(uuid, url, res);
+                data.set_result(uuid, url, res).await;
+            }
+            Some(request) = requeue_rx.recv() => {
+                data.dispatch_tx.send(request).await.unwrap();
+            }
+            _ = purge.tick() => {
+                data.purge();
             }
             Ok(()) = shutdown_rx.recv() => {
                 break;
@@ -205,41 +596,39 @@ pub async fn manager(
     Ok(())
 }
 
-#[tracing::instrument(level="debug", skip(id, order_rx, return_tx))]
+#[tracing::instrument(level="debug", skip(id, order_rx, return_tx, requeue_tx))]
 async fn worker(
     id: usize,
     order_rx: async_channel::Receiver<SingleUrlDownload>,
-    return_tx: mpsc::Sender<SingleUrlResult>
+    return_tx: mpsc::Sender<SingleUrlResult>,
+    requeue_tx: mpsc::Sender<SingleUrlDownload>
 ) -> Result<(), Box<dyn std::error::Error>> {
     loop {
         match order_rx.recv().await {
             Err(_) => { break },
             Ok(request) => {
-                let (uuid, url) = request;
+                let (uuid, url, mode, job, checks, validators, extract, attempt) = request;
                 info!("Worker {} got ({}):{}", id, uuid, url.to_string());
                 // Actually fetch
-                let value = match reqwest::get(url.clone()).await {
-                    Ok(rs) => DownloadResult::Fetched(rs.status()),
-                    Err(err) => {
-                        err.status().map_or_else(|| 
-                            if err.is_redirect() {
-                                DownloadResult::RedirectError
-                            } else if err.is_timeout() {
-                                DownloadResult::TimeOutError
-                            } else if err.is_request() {
-                                DownloadResult::RequestError
-                            } else if err.is_connect() {
-                                DownloadResult::ConnectError
-                            } else if err.is_decode() {
-                                DownloadResult::DecodeError
-                            } else {
-                                DownloadResult::UnknownError
-                            }
-                        , DownloadResult::Fetched)
+                let outcome = match mode {
+                    CheckMode::Http => fetch(url.clone(), &job, checks.as_deref(), validators.as_ref(), extract, attempt).await,
+                    mode => netcheck::check(url.clone(), &job, mode, attempt).await
+                };
+                let value = match outcome {
+                    Outcome::Done(report) => *report,
+                    // Waiting is not worth a worker
+                    Outcome::Later(when, attempt) => {
+                        debug!("Putting ({}):{} off", uuid, url);
+                        let requeue_tx = requeue_tx.clone();
+                        tokio::spawn(async move {
+                            sleep_until(when.into()).await;
+                            std::mem::drop(requeue_tx.send((uuid, url, mode, job, checks, validators, extract, attempt)).await);
+                        });
+                        continue;
                     }
                 };
 
-                info!("Obtained ({}):{} => {:?}", uuid, url.to_string(), value);
+                info!("Obtained ({}):{} => {:?}", uuid, url.to_string(), value.get_result());
                 // Build result
                 let result = (uuid, url, value);
                 if let Err(e) = return_tx.send(result).await {
@@ -251,3 +640,40 @@ async fn worker(
     info!("Worker {} terminated", id);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::StatusCode;
+
+    use crate::messages::{
+        DownloadResult,
+        Timings
+    };
+
+    fn report(url: &Url, total: u64) -> FetchReport {
+        let mut report = FetchReport::new(DownloadResult::Fetched(StatusCode::OK), Vec::new(), url.clone());
+        let total = Duration::from_millis(total);
+        report.set_timings(Timings::new(None, None, None, Some(total), total));
+        report
+    }
+
+    #[test]
+    fn cached_results_are_left_out_of_the_latencies() {
+        let urls: Vec<Url> = ["https://a.example/", "https://b.example/"].iter()
+            .map(|url| url.parse().unwrap())
+            .collect();
+        let mut request = Request::from(urls.iter()
+            .map(|url| Target::new(url.clone(), CheckMode::Http, None, None))
+            .collect::<Vec<_>>());
+        let mut cached = report(&urls[0], 5000);
+        cached.set_cached();
+        request.update(urls[0].clone(), cached);
+        request.update(urls[1].clone(), report(&urls[1], 20));
+        let status = request.fetch_done();
+        assert!(status.is_finished());
+        assert_eq!(status.get_total_summary().unwrap().get_max(), Duration::from_millis(20));
+        assert_eq!(status.get_ttfb_summary().unwrap().get_max(), Duration::from_millis(20));
+    }
+}
@@ -1,71 +1,989 @@
+use chrono::{
+    DateTime,
+    Utc
+};
 use reqwest::Url;
 use tokio::sync::oneshot;
 use uuid::Uuid;
 use warp::http::StatusCode;
 
-use std::collections::HashMap;
+use crate::{
+    client::{
+        ProxyChoice,
+        TlsOptions
+    },
+    conditional::Validators,
+    dns::HostOverrides,
+    checks::{
+        CheckOutcome,
+        ContentChecks
+    },
+    expect::{
+        Expectations,
+        JobVerdict,
+        Verdict
+    },
+    extras::RequestExtras,
+    fetch::Attempt,
+    job::JobContext,
+    links::{
+        CrawlLimits,
+        Link
+    },
+    soft404::Soft404Report,
+    tls::TlsReport
+};
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap
+    },
+    hash::{
+        Hash,
+        Hasher
+    },
+    net::IpAddr,
+    sync::Arc,
+    time::Duration
+};
 
 #[derive(Debug, Copy, Clone)]
 pub enum DownloadResult {
     Fetched(StatusCode),
     RedirectError,
+    RedirectLoop,
+    TooManyRedirects,
+    Blocked,
     TimeOutError,
     RequestError,
     ConnectError,
     DecodeError,
-    UnknownError
+    UnknownError,
+    /// The host does not exist
+    DnsNotFound,
+    /// The host exists, but has no address
+    DnsNoData,
+    /// The resolver did not answer in time
+    DnsTimeout,
+    ConnectionRefused,
+    ConnectionReset,
+    /// The TLS handshake failed for another reason than the certificate
+    TlsError,
+    CertificateInvalid,
+    BodyTooLarge,
+    /// Not fetched, robots.txt asks us not to
+    RobotsDisallowed,
+    /// The host resolved, in DNS mode. Its legacy status is 1
+    Resolved,
+    /// The port accepted a connection, in TCP mode. Its legacy status is 2
+    Connected
 }
 
+/// The status of the legacy reply, which only knows the six original
+/// errors : the others are folded into the closest of them, and told
+/// apart by the v2 reply. Checks that do not speak HTTP get statuses of
+/// their own, below any HTTP one
 impl From<DownloadResult> for i32 {
     fn from(d: DownloadResult) -> Self {
         match d {
             DownloadResult::Fetched(s) => s.as_u16().into(),
-            DownloadResult::RedirectError => -1,
+            DownloadResult::RedirectError
+                | DownloadResult::RedirectLoop
+                | DownloadResult::TooManyRedirects => -1,
             DownloadResult::TimeOutError => -2,
-            DownloadResult::RequestError => -3,
-            DownloadResult::ConnectError => -4,
-            DownloadResult::DecodeError => -5,
-            DownloadResult::UnknownError => -6
+            // Sent and cut short, or never sent at all
+            DownloadResult::RequestError
+                | DownloadResult::ConnectionReset
+                | DownloadResult::RobotsDisallowed => -3,
+            // Nothing could be reached
+            DownloadResult::ConnectError
+                | DownloadResult::Blocked
+                | DownloadResult::DnsNotFound
+                | DownloadResult::DnsNoData
+                | DownloadResult::DnsTimeout
+                | DownloadResult::ConnectionRefused
+                | DownloadResult::TlsError
+                | DownloadResult::CertificateInvalid => -4,
+            DownloadResult::DecodeError
+                | DownloadResult::BodyTooLarge => -5,
+            DownloadResult::UnknownError => -6,
+            DownloadResult::Resolved => 1,
+            DownloadResult::Connected => 2
+        }
+    }
+}
+
+impl DownloadResult {
+    /// Machine-readable name of the error, if this is one
+    pub const fn error_kind(self) -> Option<&'static str> {
+        match self {
+            Self::Fetched(_) | Self::Resolved | Self::Connected => None,
+            Self::RedirectError => Some("redirect_error"),
+            Self::TimeOutError => Some("timeout"),
+            Self::RequestError => Some("request_error"),
+            Self::ConnectError => Some("connect_error"),
+            Self::DecodeError => Some("decode_error"),
+            Self::UnknownError => Some("unknown_error"),
+            Self::RedirectLoop => Some("redirect_loop"),
+            Self::TooManyRedirects => Some("too_many_redirects"),
+            Self::Blocked => Some("blocked"),
+            Self::DnsNotFound => Some("dns_not_found"),
+            Self::DnsNoData => Some("dns_no_data"),
+            Self::DnsTimeout => Some("dns_timeout"),
+            Self::ConnectionRefused => Some("connection_refused"),
+            Self::ConnectionReset => Some("connection_reset"),
+            Self::TlsError => Some("tls_error"),
+            Self::CertificateInvalid => Some("certificate_invalid"),
+            Self::BodyTooLarge => Some("body_too_large"),
+            Self::RobotsDisallowed => Some("robots_disallowed")
+        }
+    }
+
+    /// Whether trying again later could give another outcome
+    pub const fn is_transient(self) -> bool {
+        matches!(
+            self,
+            Self::TimeOutError | Self::ConnectError | Self::DnsTimeout
+                | Self::ConnectionRefused | Self::ConnectionReset
+        )
+    }
+}
+
+/// One step of a redirect chain : the redirection status
+/// and where the `Location` header pointed to
+#[derive(Debug, Clone)]
+pub struct RedirectHop {
+    status: StatusCode,
+    location: Url
+}
+
+impl RedirectHop {
+    pub const fn new(status: StatusCode, location: Url) -> Self {
+        Self { status, location }
+    }
+
+    pub const fn get_status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub const fn get_location(&self) -> &Url {
+        &self.location
+    }
+}
+
+/// How long each phase of a fetch took. The DNS, connect and TLS phases
+/// are those of the connection the fetch opened, and are left out when it
+/// reused one, or went through a proxy. The time to first byte starts once
+/// the connection is ready, and stops with the headers of the response.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    dns: Option<Duration>,
+    connect: Option<Duration>,
+    tls: Option<Duration>,
+    ttfb: Option<Duration>,
+    total: Duration
+}
+
+impl Timings {
+    pub const fn new(
+        dns: Option<Duration>,
+        connect: Option<Duration>,
+        tls: Option<Duration>,
+        ttfb: Option<Duration>,
+        total: Duration
+    ) -> Self {
+        Self { dns, connect, tls, ttfb, total }
+    }
+
+    pub const fn get_dns(&self) -> Option<Duration> {
+        self.dns
+    }
+
+    pub const fn get_connect(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    pub const fn get_tls(&self) -> Option<Duration> {
+        self.tls
+    }
+
+    pub const fn get_ttfb(&self) -> Option<Duration> {
+        self.ttfb
+    }
+
+    pub const fn get_total(&self) -> Duration {
+        self.total
+    }
+}
+
+/// Percentiles over the durations of a whole job
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySummary {
+    p50: Duration,
+    p95: Duration,
+    max: Duration
+}
+
+/// Nearest-rank percentile of non-empty sorted samples: the 0th
+/// is the smallest sample, the 100th the largest
+fn nearest_rank(sorted: &[Duration], percentile: usize) -> Duration {
+    sorted[(percentile * sorted.len()).div_ceil(100).clamp(1, sorted.len()) - 1]
+}
+
+impl LatencySummary {
+    /// Nearest-rank percentiles, `None` if there is nothing to summarize
+    pub fn from_samples(samples: &[Duration]) -> Option<Self> {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let max = *sorted.last()?;
+        Some(Self {
+            p50: nearest_rank(&sorted, 50),
+            p95: nearest_rank(&sorted, 95),
+            max
+        })
+    }
+
+    pub const fn get_p50(&self) -> Duration {
+        self.p50
+    }
+
+    pub const fn get_p95(&self) -> Duration {
+        self.p95
+    }
+
+    pub const fn get_max(&self) -> Duration {
+        self.max
+    }
+}
+
+/// Everything a worker learned about a single URL
+#[derive(Debug, Clone)]
+pub struct FetchReport {
+    result: DownloadResult,
+    message: Option<String>,
+    redirects: Vec<RedirectHop>,
+    final_url: Url,
+    content_type: Option<String>,
+    content_length: Option<u64>,
+    attempts: u32,
+    timings: Timings,
+    fetched_at: DateTime<Utc>,
+    checks: Option<CheckOutcome>,
+    soft_404: Option<Soft404Report>,
+    verdict: Option<Verdict>,
+    cached: bool,
+    validators: Option<Validators>,
+    unchanged: bool,
+    tls: Option<TlsReport>,
+    mode: CheckMode,
+    addresses: Vec<IpAddr>,
+    address: Option<IpAddr>,
+    routes: Vec<RouteReport>,
+    links: Vec<Link>,
+    linked_from: Vec<Url>,
+    lastmod: Option<String>,
+    labels: HashMap<String, String>
+}
+
+impl FetchReport {
+    pub fn new(result: DownloadResult, redirects: Vec<RedirectHop>, final_url: Url) -> Self {
+        Self {
+            result,
+            message: None,
+            redirects,
+            final_url,
+            content_type: None,
+            content_length: None,
+            attempts: 1,
+            timings: Timings::default(),
+            fetched_at: Utc::now(),
+            checks: None,
+            soft_404: None,
+            verdict: None,
+            cached: false,
+            validators: None,
+            unchanged: false,
+            tls: None,
+            mode: CheckMode::Http,
+            addresses: Vec::new(),
+            address: None,
+            routes: Vec::new(),
+            links: Vec::new(),
+            linked_from: Vec::new(),
+            lastmod: None,
+            labels: HashMap::new()
+        }
+    }
+
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = Some(message);
+        self
+    }
+
+    pub fn with_content(mut self, content_type: Option<String>, content_length: Option<u64>) -> Self {
+        self.content_type = content_type;
+        self.content_length = content_length;
+        self
+    }
+
+    pub fn with_checks(mut self, checks: CheckOutcome) -> Self {
+        self.checks = Some(checks);
+        self
+    }
+
+    pub fn with_validators(mut self, validators: Option<Validators>) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    /// Mark the report as a `304` to a conditional request
+    pub const fn with_unchanged(mut self) -> Self {
+        self.unchanged = true;
+        self
+    }
+
+    pub fn set_attempt(&mut self, attempts: u32, fetched_at: DateTime<Utc>) {
+        self.attempts = attempts;
+        self.fetched_at = fetched_at;
+    }
+
+    pub fn set_timings(&mut self, timings: Timings) {
+        self.timings = timings;
+    }
+
+    pub fn set_soft_404(&mut self, soft_404: Soft404Report) {
+        self.soft_404 = Some(soft_404);
+    }
+
+    pub fn set_verdict(&mut self, verdict: Verdict) {
+        self.verdict = Some(verdict);
+    }
+
+    pub fn set_tls(&mut self, tls: TlsReport) {
+        self.tls = Some(tls);
+    }
+
+    /// Mark the report as coming from an earlier fetch
+    pub fn set_cached(&mut self) {
+        self.cached = true;
+    }
+
+    pub const fn get_result(&self) -> DownloadResult {
+        self.result
+    }
+
+    pub fn get_message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn get_redirects(&self) -> &[RedirectHop] {
+        &self.redirects
+    }
+
+    pub const fn get_final_url(&self) -> &Url {
+        &self.final_url
+    }
+
+    pub fn get_content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub const fn get_content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    pub const fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub const fn get_timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    pub const fn get_fetched_at(&self) -> DateTime<Utc> {
+        self.fetched_at
+    }
+
+    pub const fn get_checks(&self) -> Option<&CheckOutcome> {
+        self.checks.as_ref()
+    }
+
+    pub const fn get_soft_404(&self) -> Option<&Soft404Report> {
+        self.soft_404.as_ref()
+    }
+
+    pub const fn get_verdict(&self) -> Option<&Verdict> {
+        self.verdict.as_ref()
+    }
+
+    pub const fn is_cached(&self) -> bool {
+        self.cached
+    }
+
+    pub const fn get_validators(&self) -> Option<&Validators> {
+        self.validators.as_ref()
+    }
+
+    pub const fn is_unchanged(&self) -> bool {
+        self.unchanged
+    }
+
+    pub const fn get_tls(&self) -> Option<&TlsReport> {
+        self.tls.as_ref()
+    }
+
+    pub const fn with_mode(mut self, mode: CheckMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Where the host resolved to
+    pub fn with_addresses(mut self, addresses: Vec<IpAddr>) -> Self {
+        self.addresses = addresses;
+        self
+    }
+
+    /// Which address the connection was made to
+    pub fn set_address(&mut self, address: Option<IpAddr>) {
+        self.address = address;
+    }
+
+    pub const fn get_mode(&self) -> CheckMode {
+        self.mode
+    }
+
+    pub fn get_addresses(&self) -> &[IpAddr] {
+        &self.addresses
+    }
+
+    pub const fn get_address(&self) -> Option<IpAddr> {
+        self.address
+    }
+
+    pub fn set_routes(&mut self, routes: Vec<RouteReport>) {
+        self.routes = routes;
+    }
+
+    pub fn get_routes(&self) -> &[RouteReport] {
+        &self.routes
+    }
+
+    pub fn set_links(&mut self, links: Vec<Link>) {
+        self.links = links;
+    }
+
+    /// The links found on the page, which are not reported themselves
+    pub fn take_links(&mut self) -> Vec<Link> {
+        std::mem::take(&mut self.links)
+    }
+
+    pub fn set_linked_from(&mut self, pages: Vec<Url>) {
+        self.linked_from = pages;
+    }
+
+    pub fn get_linked_from(&self) -> &[Url] {
+        &self.linked_from
+    }
+
+    pub fn set_lastmod(&mut self, lastmod: Option<String>) {
+        self.lastmod = lastmod;
+    }
+
+    pub fn get_lastmod(&self) -> Option<&str> {
+        self.lastmod.as_deref()
+    }
+
+    pub fn set_labels(&mut self, labels: HashMap<String, String>) {
+        self.labels = labels;
+    }
+
+    pub const fn get_labels(&self) -> &HashMap<String, String> {
+        &self.labels
+    }
+}
+
+/// Whether URLs are also fetched over each way to reach their host
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum AddressProbe {
+    #[default]
+    Off,
+    /// Once over IPv4 and once over IPv6
+    Families,
+    /// Once against every address the host resolves to
+    Addresses
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Family {
+    V4,
+    V6
+}
+
+impl Family {
+    pub const fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Self::V4,
+            IpAddr::V6(_) => Self::V6
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::V4 => "ipv4",
+            Self::V6 => "ipv6"
+        }
+    }
+}
+
+/// The outcome of a fetch pinned to an address family, or to one address
+#[derive(Debug, Clone)]
+pub struct RouteReport {
+    family: Family,
+    address: Option<IpAddr>,
+    result: DownloadResult,
+    message: Option<String>,
+    total: Duration
+}
+
+impl RouteReport {
+    pub const fn new(
+        family: Family,
+        address: Option<IpAddr>,
+        result: DownloadResult,
+        message: Option<String>,
+        total: Duration
+    ) -> Self {
+        Self { family, address, result, message, total }
+    }
+
+    pub const fn get_family(&self) -> Family {
+        self.family
+    }
+
+    pub const fn get_address(&self) -> Option<IpAddr> {
+        self.address
+    }
+
+    pub const fn get_result(&self) -> DownloadResult {
+        self.result
+    }
+
+    pub fn get_message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub const fn get_total(&self) -> Duration {
+        self.total
+    }
+}
+
+/// How a job wants its URLs to be fetched
+// Independent switches, one per job option
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    follow_redirects: bool,
+    max_redirects: usize,
+    retries: u32,
+    detect_soft_404: bool,
+    extras: RequestExtras,
+    proxy: ProxyChoice,
+    tls: TlsOptions,
+    use_cache: bool,
+    conditional: bool,
+    cert_warning_days: Option<u32>,
+    address_probe: AddressProbe,
+    overrides: HostOverrides,
+    seeds: bool,
+    sort_query: bool,
+    crawl: Option<CrawlLimits>,
+    robots: bool
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            follow_redirects: true,
+            max_redirects: 10,
+            retries: 0,
+            detect_soft_404: false,
+            extras: RequestExtras::default(),
+            proxy: ProxyChoice::Default,
+            tls: TlsOptions::default(),
+            use_cache: true,
+            conditional: false,
+            cert_warning_days: None,
+            address_probe: AddressProbe::Off,
+            overrides: HostOverrides::new(),
+            seeds: false,
+            sort_query: false,
+            crawl: None,
+            robots: true
+        }
+    }
+}
+
+impl FetchOptions {
+    pub const fn with_redirects(mut self, follow_redirects: bool, max_redirects: usize) -> Self {
+        self.follow_redirects = follow_redirects;
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    pub const fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub const fn with_soft_404(mut self, detect_soft_404: bool) -> Self {
+        self.detect_soft_404 = detect_soft_404;
+        self
+    }
+
+    pub fn with_extras(mut self, extras: RequestExtras) -> Self {
+        self.extras = extras;
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: ProxyChoice) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub const fn with_cache(mut self, use_cache: bool) -> Self {
+        self.use_cache = use_cache;
+        self
+    }
+
+    pub const fn with_conditional(mut self, conditional: bool) -> Self {
+        self.conditional = conditional;
+        self
+    }
+
+    pub const fn with_cert_warning(mut self, days: Option<u32>) -> Self {
+        self.cert_warning_days = days;
+        self
+    }
+
+    pub const fn with_address_probe(mut self, address_probe: AddressProbe) -> Self {
+        self.address_probe = address_probe;
+        self
+    }
+
+    pub fn with_overrides(mut self, overrides: HostOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Submitted URLs are pages whose links are checked too, and
+    /// links are normalized like the URLs were
+    pub const fn with_seeds(mut self, seeds: bool, sort_query: bool) -> Self {
+        self.seeds = seeds;
+        self.sort_query = sort_query;
+        self
+    }
+
+    /// Links found on seeds are searched too, within `crawl`
+    pub fn with_crawl(mut self, crawl: Option<CrawlLimits>) -> Self {
+        self.crawl = crawl;
+        self
+    }
+
+    /// Whether robots.txt is to be followed
+    pub const fn with_robots(mut self, robots: bool) -> Self {
+        self.robots = robots;
+        self
+    }
+
+    pub const fn follows_redirects(&self) -> bool {
+        self.follow_redirects
+    }
+
+    pub const fn get_max_redirects(&self) -> usize {
+        self.max_redirects
+    }
+
+    pub const fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub const fn detects_soft_404(&self) -> bool {
+        self.detect_soft_404
+    }
+
+    pub const fn get_extras(&self) -> &RequestExtras {
+        &self.extras
+    }
+
+    pub const fn get_proxy(&self) -> &ProxyChoice {
+        &self.proxy
+    }
+
+    pub const fn get_tls(&self) -> &TlsOptions {
+        &self.tls
+    }
+
+    /// Whether results can be shared with other jobs. Anything fetched
+    /// with credentials, a client certificate or custom headers may
+    /// depend on who asked, and overridden hosts are not the real ones.
+    pub fn is_shareable(&self) -> bool {
+        self.extras.is_empty() && self.tls.get_identity().is_none() && self.overrides.is_empty()
+    }
+
+    pub const fn uses_cache(&self) -> bool {
+        self.use_cache
+    }
+
+    pub const fn is_conditional(&self) -> bool {
+        self.conditional
+    }
+
+    pub const fn get_cert_warning_days(&self) -> Option<u32> {
+        self.cert_warning_days
+    }
+
+    pub const fn get_address_probe(&self) -> AddressProbe {
+        self.address_probe
+    }
+
+    pub const fn get_overrides(&self) -> &HostOverrides {
+        &self.overrides
+    }
+
+    pub const fn has_seeds(&self) -> bool {
+        self.seeds || self.crawl.is_some()
+    }
+
+    pub const fn get_crawl(&self) -> Option<&CrawlLimits> {
+        self.crawl.as_ref()
+    }
+
+    pub const fn follows_robots(&self) -> bool {
+        self.robots
+    }
+
+    pub const fn sorts_query(&self) -> bool {
+        self.sort_query
+    }
+
+    /// Whether the client shared by jobs that change nothing will do
+    pub fn uses_default_client(&self) -> bool {
+        matches!(self.proxy, ProxyChoice::Default)
+            && self.tls == TlsOptions::default()
+            && self.overrides.is_empty()
+    }
+}
+
+// Everything that can change the outcome of a fetch
+impl Hash for FetchOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.follow_redirects.hash(state);
+        self.max_redirects.hash(state);
+        self.retries.hash(state);
+        self.detect_soft_404.hash(state);
+        self.proxy.hash(state);
+        self.tls.hash(state);
+        self.conditional.hash(state);
+        self.cert_warning_days.hash(state);
+        self.address_probe.hash(state);
+        self.robots.hash(state);
+        // In a stable order
+        self.overrides.iter().collect::<BTreeMap<_, _>>().hash(state);
+    }
+}
+
+/// What a worker looks for in a page once fetched
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Extraction {
+    #[default]
+    Off,
+    /// Links to other pages and resources
+    Links,
+    /// Pages and sitemaps listed in a sitemap
+    Sitemap
+}
+
+/// What is checked about a URL
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum CheckMode {
+    /// Fetch it
+    #[default]
+    Http,
+    /// Only resolve its host
+    Dns,
+    /// Only open a connection to its host and port
+    Tcp
+}
+
+impl CheckMode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Dns => "dns",
+            Self::Tcp => "tcp"
+        }
+    }
+}
+
+impl std::str::FromStr for CheckMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http" => Ok(Self::Http),
+            "dns" => Ok(Self::Dns),
+            "tcp" => Ok(Self::Tcp),
+            _ => Err(format!("unknown mode \"{s}\", expected \"http\", \"dns\" or \"tcp\""))
         }
     }
 }
 
+/// A URL submitted in a job, and what is expected of it
+#[derive(Debug)]
+pub struct Target {
+    url: Url,
+    mode: CheckMode,
+    expect: Option<Expectations>,
+    checks: Option<Arc<ContentChecks>>,
+    sitemap: bool,
+    labels: HashMap<String, String>
+}
+
+impl Target {
+    pub fn new(
+        url: Url,
+        mode: CheckMode,
+        expect: Option<Expectations>,
+        checks: Option<Arc<ContentChecks>>
+    ) -> Self {
+        Self { url, mode, expect, checks, sitemap: false, labels: HashMap::new() }
+    }
+
+    /// The URL is a sitemap, whose pages are added to the job
+    pub const fn with_sitemap(mut self, sitemap: bool) -> Self {
+        self.sitemap = sitemap;
+        self
+    }
+
+    pub const fn is_sitemap(&self) -> bool {
+        self.sitemap
+    }
+
+    /// Free-form labels, handed back with the result of the URL
+    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub const fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    pub const fn get_mode(&self) -> CheckMode {
+        self.mode
+    }
+
+    pub const fn get_checks(&self) -> Option<&Arc<ContentChecks>> {
+        self.checks.as_ref()
+    }
+
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn explode(self) -> (Url, Option<Expectations>, Option<Arc<ContentChecks>>, HashMap<String, String>) {
+        (self.url, self.expect, self.checks, self.labels)
+    }
+}
+
 #[derive(Debug)]
 pub struct RequestMessage {
-    urls: Vec<Url>,
-    result_tx: oneshot::Sender<Uuid>
+    urls: Vec<Target>,
+    options: FetchOptions,
+    result_tx: oneshot::Sender<Result<Uuid, String>>
 }
 
 impl RequestMessage {
     pub fn new(
-        urls: Vec<Url>,
-        result_tx: oneshot::Sender<Uuid>
+        urls: Vec<Target>,
+        options: FetchOptions,
+        result_tx: oneshot::Sender<Result<Uuid, String>>
     ) -> Self {
-        Self { urls, result_tx }
+        Self { urls, options, result_tx }
     }
 
     // it's a destructor, it's not missing const : it can't be
     #[allow(clippy::missing_const_for_fn)]
-    pub fn explode(self) -> (Vec<Url>, oneshot::Sender<Uuid>) {
-        (self.urls, self.result_tx)
+    pub fn explode(self) -> (Vec<Target>, FetchOptions, oneshot::Sender<Result<Uuid, String>>) {
+        (self.urls, self.options, self.result_tx)
     }
 }
 
 #[derive(Debug)]
 pub struct StatusReplyMessage {
     finished: bool,
-    results: HashMap<Url, DownloadResult>
+    results: HashMap<Url, FetchReport>,
+    total: Option<LatencySummary>,
+    ttfb: Option<LatencySummary>,
+    verdict: Option<VerdictSummary>
+}
+
+/// Overall verdict of a job, with how many URLs passed and failed so far
+#[derive(Debug, Clone, Copy)]
+pub struct VerdictSummary {
+    verdict: JobVerdict,
+    passed: usize,
+    failed: usize
+}
+
+impl VerdictSummary {
+    pub const fn new(verdict: JobVerdict, passed: usize, failed: usize) -> Self {
+        Self { verdict, passed, failed }
+    }
+
+    pub const fn get_verdict(&self) -> JobVerdict {
+        self.verdict
+    }
+
+    pub const fn get_passed(&self) -> usize {
+        self.passed
+    }
+
+    pub const fn get_failed(&self) -> usize {
+        self.failed
+    }
 }
 
 impl StatusReplyMessage {
-    pub const fn new(finished: bool, results: HashMap<Url, DownloadResult>) -> Self {
-        Self { finished, results }
+    pub const fn new(
+        finished: bool,
+        results: HashMap<Url, FetchReport>,
+        total: Option<LatencySummary>,
+        ttfb: Option<LatencySummary>,
+        verdict: Option<VerdictSummary>
+    ) -> Self {
+        Self { finished, results, total, ttfb, verdict }
     }
     pub const fn is_finished(&self) -> bool {
         self.finished
     }
 
-    pub fn into_results(self) -> HashMap<Url, DownloadResult> {
+    pub const fn get_total_summary(&self) -> Option<LatencySummary> {
+        self.total
+    }
+
+    pub const fn get_ttfb_summary(&self) -> Option<LatencySummary> {
+        self.ttfb
+    }
+
+    pub const fn get_verdict(&self) -> Option<VerdictSummary> {
+        self.verdict
+    }
+
+    pub fn into_results(self) -> HashMap<Url, FetchReport> {
         self.results
     }
 
@@ -76,6 +994,69 @@ impl StatusReplyMessage {
 
 pub type StatusRequestMessage = (Uuid, oneshot::Sender<Option<StatusReplyMessage>>);
 
-pub type SingleUrlDownload = (Uuid, Url);
+pub type SingleUrlDownload = (Uuid, Url, CheckMode, Arc<JobContext>, Option<Arc<ContentChecks>>, Option<Validators>, Extraction, Attempt);
+
+pub type SingleUrlResult = (Uuid, Url, FetchReport);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn legacy_codes_stay_the_original_ones() {
+        let code = |result: DownloadResult| i32::from(result);
+        assert_eq!(code(DownloadResult::Fetched(StatusCode::NOT_FOUND)), 404);
+        assert_eq!(code(DownloadResult::TooManyRedirects), code(DownloadResult::RedirectError));
+        assert_eq!(code(DownloadResult::DnsNotFound), code(DownloadResult::ConnectError));
+        assert_eq!(code(DownloadResult::CertificateInvalid), code(DownloadResult::ConnectError));
+        assert_eq!(code(DownloadResult::BodyTooLarge), code(DownloadResult::DecodeError));
+        for result in [
+            DownloadResult::RedirectLoop, DownloadResult::Blocked, DownloadResult::DnsNoData, DownloadResult::DnsTimeout,
+            DownloadResult::ConnectionRefused, DownloadResult::ConnectionReset, DownloadResult::TlsError,
+            DownloadResult::RobotsDisallowed
+        ] {
+            assert!((-6..=-1).contains(&code(result)), "{result:?}");
+        }
+    }
 
-pub type SingleUrlResult = (Uuid, Url, DownloadResult);
+    #[test]
+    fn other_checks_have_legacy_statuses_of_their_own() {
+        let resolved = i32::from(DownloadResult::Resolved);
+        let connected = i32::from(DownloadResult::Connected);
+        assert_ne!(resolved, connected);
+        for code in [resolved, connected] {
+            assert!(code > 0 && code < 100, "{code}");
+        }
+    }
+
+    #[test]
+    fn ranks_at_the_ends_are_the_extremes() {
+        let sorted = millis(&[10, 20, 30, 40]);
+        assert_eq!(nearest_rank(&sorted, 0), Duration::from_millis(10));
+        assert_eq!(nearest_rank(&sorted, 100), Duration::from_millis(40));
+        assert_eq!(nearest_rank(&millis(&[7]), 0), Duration::from_millis(7));
+        assert_eq!(nearest_rank(&millis(&[7]), 100), Duration::from_millis(7));
+    }
+
+    #[test]
+    fn ranks_round_up() {
+        let sorted = millis(&[10, 20, 30, 40]);
+        assert_eq!(nearest_rank(&sorted, 25), Duration::from_millis(10));
+        assert_eq!(nearest_rank(&sorted, 26), Duration::from_millis(20));
+        assert_eq!(nearest_rank(&sorted, 50), Duration::from_millis(20));
+        assert_eq!(nearest_rank(&sorted, 95), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn summaries_sort_their_samples() {
+        assert!(LatencySummary::from_samples(&[]).is_none());
+        let summary = LatencySummary::from_samples(&millis(&[50, 10, 40, 20, 30])).unwrap();
+        assert_eq!(summary.get_p50(), Duration::from_millis(30));
+        assert_eq!(summary.get_p95(), Duration::from_millis(50));
+        assert_eq!(summary.get_max(), Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,116 @@
+//! DNS and TCP checks
+//!
+//! Sometimes all we need to know is whether a name resolves or whether a
+//! port is open. These checks stop where a fetch would carry on with a
+//! handshake, and are reported like any fetch. They follow the egress
+//! policy, but never go through a proxy.
+
+use chrono::Utc;
+use reqwest::Url;
+use tokio::{
+    net::TcpStream,
+    time::timeout
+};
+use tracing::debug;
+
+use std::{
+    io,
+    net::{
+        IpAddr,
+        SocketAddr
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::{
+    egress::Blocked,
+    fetch::{
+        classify_io,
+        Attempt,
+        Outcome
+    },
+    job::JobContext,
+    messages::{
+        CheckMode,
+        DownloadResult,
+        FetchReport,
+        Timings
+    }
+};
+
+/// How long the resolver, and then every connection, gets
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn classify(err: &io::Error) -> DownloadResult {
+    if matches!(err.get_ref(), Some(inner) if inner.is::<Blocked>()) {
+        return DownloadResult::Blocked;
+    }
+    classify_io(err).unwrap_or(DownloadResult::ConnectError)
+}
+
+/// Connect to the first of `addrs` that accepts, or tell why none did
+async fn connect(addrs: &[SocketAddr]) -> Result<(Duration, IpAddr), (DownloadResult, String)> {
+    let mut last = (DownloadResult::ConnectError, String::from("no address to connect to"));
+    for addr in addrs {
+        let start = Instant::now();
+        match timeout(CHECK_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Ok((start.elapsed(), addr.ip())),
+            Ok(Err(e)) => last = (classify(&e), format!("{addr}: {e}")),
+            Err(_) => last = (DownloadResult::TimeOutError, format!("{addr}: connection timed out"))
+        }
+    }
+    Err(last)
+}
+
+async fn check_once(url: &Url, job: &JobContext, mode: CheckMode) -> FetchReport {
+    let start = Instant::now();
+    let failed = |result, message: String| {
+        let mut report = FetchReport::new(result, Vec::new(), url.clone())
+            .with_mode(mode)
+            .with_message(message);
+        report.set_timings(Timings::new(None, None, None, None, start.elapsed()));
+        report
+    };
+    let addrs = match timeout(CHECK_TIMEOUT, job.resolve(url)).await {
+        Ok(Ok(addrs)) => addrs,
+        Ok(Err(e)) => return failed(classify(&e), e.to_string()),
+        Err(_) => return failed(DownloadResult::DnsTimeout, String::from("no answer from the resolver"))
+    };
+    let dns = start.elapsed();
+    let addresses: Vec<IpAddr> = addrs.iter().map(SocketAddr::ip).collect();
+
+    let (result, connect, address) = match mode {
+        CheckMode::Tcp => match connect(&addrs).await {
+            Ok((connect, address)) => (DownloadResult::Connected, Some(connect), Some(address)),
+            Err((result, message)) => {
+                let mut report = failed(result, message).with_addresses(addresses);
+                report.set_timings(Timings::new(Some(dns), None, None, None, start.elapsed()));
+                return report;
+            }
+        },
+        _ => (DownloadResult::Resolved, None, None)
+    };
+    let mut report = FetchReport::new(result, Vec::new(), url.clone())
+        .with_mode(mode)
+        .with_addresses(addresses);
+    report.set_address(address);
+    report.set_timings(Timings::new(Some(dns), connect, None, None, start.elapsed()));
+    report
+}
+
+/// Resolve the host of `url` and, in TCP mode, connect to its port. Like
+/// fetches, checks that fail are put off when the job allows to try again.
+pub async fn check(url: Url, job: &JobContext, mode: CheckMode, mut attempt: Attempt) -> Outcome {
+    let number = attempt.begin();
+    let fetched_at = Utc::now();
+    let mut report = check_once(&url, job, mode).await;
+    if number <= job.get_options().get_retries() && report.get_result().is_transient() {
+        debug!("Attempt {} on {} failed, retrying", number, url);
+        return attempt.retry();
+    }
+    report.set_attempt(number, fetched_at);
+    Outcome::Done(Box::new(report))
+}
@@ -0,0 +1,331 @@
+//! robots.txt compliance
+//!
+//! Jobs routinely hit sites that are not ours, so we play by their rules:
+//! the `robots.txt` of every origin is fetched once in a while and URLs it
+//! disallows for our user agent are not fetched at all. A `Crawl-delay`
+//! spaces out requests to its origin, whichever jobs they come from: each
+//! request books a turn, and is put off until it comes.
+//!
+//! Rules are matched as described in RFC 9309: the longest matching
+//! pattern wins, and `Allow` wins ties.
+
+use reqwest::{
+    header::LOCATION,
+    Response,
+    StatusCode,
+    Url
+};
+use tokio::{
+    sync::{
+        Mutex,
+        OnceCell
+    },
+    time::timeout
+};
+use tracing::debug;
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::{
+    client::HttpClient,
+    egress::EgressPolicy
+};
+
+/// How long rules are trusted before being fetched again
+const ROBOTS_TTL: Duration = Duration::from_hours(24);
+/// How long we go by what we made of a failure to get the rules
+const ROBOTS_ERROR_TTL: Duration = Duration::from_mins(5);
+/// How long a server gets to hand over its rules
+const ROBOTS_TIMEOUT: Duration = Duration::from_secs(10);
+/// Only so much of a robots.txt is read, like the RFC allows
+const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+const MAX_ROBOTS_REDIRECTS: usize = 5;
+/// Longer delays are cut down to this
+const MAX_CRAWL_DELAY: Duration = Duration::from_mins(1);
+
+/// What an origin allows us to fetch
+#[derive(Debug, Default)]
+pub struct RobotsRules {
+    // Whether the path is allowed, and its pattern
+    rules: Vec<(bool, String)>,
+    delay: Option<Duration>
+}
+
+impl RobotsRules {
+    /// Nothing is allowed, as when the server fails to say
+    fn disallow_all() -> Self {
+        Self { rules: vec![(false, String::from("/"))], delay: None }
+    }
+
+    /// The rules of `text` that apply to `agent`, a product token
+    pub fn parse(text: &str, agent: &str) -> Self {
+        // Groups meant for us, and for everybody
+        let mut ours = Self::default();
+        let mut anyone = Self::default();
+        let mut matched = false;
+        let (mut for_us, mut for_anyone) = (false, false);
+        // Consecutive user-agent lines share the rules that follow them
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            if key == "user-agent" {
+                if !in_agents {
+                    (for_us, for_anyone) = (false, false);
+                    in_agents = true;
+                }
+                // Some add a version to the product token, like user agents do
+                let name = value.split(['/', ' ']).next().unwrap_or_default();
+                if name == "*" {
+                    for_anyone = true;
+                } else if !name.is_empty() && name.eq_ignore_ascii_case(agent) {
+                    for_us = true;
+                    matched = true;
+                }
+                continue;
+            }
+            in_agents = false;
+            for group in [(for_us, &mut ours), (for_anyone, &mut anyone)]
+                .into_iter()
+                .filter_map(|(applies, group)| applies.then_some(group)) {
+                match key.as_str() {
+                    // An empty disallow allows everything
+                    "allow" | "disallow" if !value.is_empty() => group.rules.push((key == "allow", value.into())),
+                    "crawl-delay" => group.delay = value.parse::<f64>().ok()
+                        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                        .map(|secs| Duration::from_secs_f64(secs).min(MAX_CRAWL_DELAY)),
+                    _ => {}
+                }
+            }
+        }
+        if matched { ours } else { anyone }
+    }
+
+    /// Whether `url` can be fetched
+    pub fn allows(&self, url: &Url) -> bool {
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path = format!("{path}?{query}");
+        }
+        if path == "/robots.txt" {
+            return true;
+        }
+        self.rules.iter()
+            .filter(|(_, pattern)| matches(pattern, &path))
+            // Longest pattern first, allow first among equals
+            .max_by_key(|(allow, pattern)| (pattern.len(), *allow))
+            .is_none_or(|(allow, _)| *allow)
+    }
+
+    pub const fn get_delay(&self) -> Option<Duration> {
+        self.delay
+    }
+}
+
+/// Whether `path` starts with `pattern`, where `*` stands for
+/// anything and a final `$` for the end of the path
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = pattern.strip_suffix('$')
+        .map_or((pattern, false), |pattern| (pattern, true));
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        // The last part has to end the path if anchored, so take
+        // the last place it appears at
+        let found = if anchored && i + 1 == parts.len() {
+            rest.rfind(part)
+        } else {
+            rest.find(part)
+        };
+        let Some(at) = found else {
+            return false;
+        };
+        rest = &rest[at + part.len()..];
+    }
+    !anchored || rest.is_empty()
+}
+
+// The rules, and until when they hold
+type RulesCell = Arc<OnceCell<(Instant, Arc<RobotsRules>)>>;
+
+/// Rules of every origin met, and when each may be hit next
+#[derive(Debug)]
+pub struct RobotsCache {
+    agent: String,
+    rules: Mutex<HashMap<String, RulesCell>>,
+    next_turn: Mutex<HashMap<String, Instant>>
+}
+
+impl RobotsCache {
+    /// Rules are looked up for the product token of `user_agent`
+    pub fn new(user_agent: &str) -> Self {
+        let agent = user_agent.split(['/', ' ']).next().unwrap_or_default().to_string();
+        Self {
+            agent,
+            rules: Mutex::new(HashMap::new()),
+            next_turn: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// The rules for the origin of `url`, fetched with `client` if we
+    /// have none or they are too old
+    pub async fn rules(&self, client: &HttpClient, policy: &EgressPolicy, url: &Url) -> Arc<RobotsRules> {
+        let origin = url.origin().ascii_serialization();
+        let mut rules = self.rules.lock().await;
+        let cell = rules.entry(origin).or_default();
+        if cell.get().is_some_and(|(expires, _)| *expires <= Instant::now()) {
+            *cell = RulesCell::default();
+        }
+        let cell = Arc::clone(cell);
+        drop(rules);
+        cell.get_or_init(|| async {
+            let (rules, ttl) = self.fetch(client, policy, url).await;
+            (Instant::now() + ttl, Arc::new(rules))
+        }).await.1.clone()
+    }
+
+    /// The rules at the origin of `url`, and how long they hold. Failures
+    /// only hold for a while, so that a server that comes back is asked again.
+    async fn fetch(&self, client: &HttpClient, policy: &EgressPolicy, url: &Url) -> (RobotsRules, Duration) {
+        let Ok(mut current) = url.join("/robots.txt") else {
+            return (RobotsRules::default(), ROBOTS_TTL);
+        };
+        for _ in 0..=MAX_ROBOTS_REDIRECTS {
+            // The fetch itself will tell why it cannot go there
+            if policy.check_url(&current).is_err() {
+                return (RobotsRules::default(), ROBOTS_TTL);
+            }
+            let response = match timeout(ROBOTS_TIMEOUT, client.send(client.get(current.clone()))).await {
+                Ok(Ok(response)) => response,
+                // Neither can we tell for a server we cannot reach
+                Ok(Err(e)) => {
+                    debug!("Unable to fetch {}: {}", current, e);
+                    return (RobotsRules::default(), ROBOTS_ERROR_TTL);
+                },
+                Err(_) => {
+                    debug!("Timed out fetching {}", current);
+                    return (RobotsRules::default(), ROBOTS_ERROR_TTL);
+                }
+            };
+            let status = response.status();
+            if status.is_redirection() {
+                let next = response.headers().get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| current.join(location).ok());
+                match next {
+                    Some(next) => {
+                        current = next;
+                        continue;
+                    },
+                    None => return (RobotsRules::default(), ROBOTS_TTL)
+                }
+            }
+            return match status {
+                status if status.is_success() => match timeout(ROBOTS_TIMEOUT, read(response)).await {
+                    Ok(Ok(body)) => (RobotsRules::parse(&String::from_utf8_lossy(&body), &self.agent), ROBOTS_TTL),
+                    _ => (RobotsRules::default(), ROBOTS_ERROR_TTL)
+                },
+                // A server that fails to say what is allowed allows nothing
+                status if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+                    debug!("{} answered {}, disallowing everything", current, status);
+                    (RobotsRules::disallow_all(), ROBOTS_ERROR_TTL)
+                },
+                // No rules, no restrictions
+                _ => (RobotsRules::default(), ROBOTS_TTL)
+            };
+        }
+        (RobotsRules::default(), ROBOTS_TTL)
+    }
+
+    /// Book the next turn of the origin of `url`, as far as its
+    /// `Crawl-delay` goes, and tell when it comes
+    pub async fn book_turn(&self, url: &Url, delay: Duration) -> Instant {
+        let mut next_turn = self.next_turn.lock().await;
+        let now = Instant::now();
+        // Origins whose turn has come would start over from now anyway
+        next_turn.retain(|_, next| *next > now);
+        let next = next_turn.entry(url.origin().ascii_serialization()).or_insert(now);
+        let turn = (*next).max(now);
+        *next = turn + delay;
+        drop(next_turn);
+        turn
+    }
+}
+
+/// The body of `response`, up to `MAX_ROBOTS_SIZE`
+async fn read(mut response: Response) -> reqwest::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        let room = MAX_ROBOTS_SIZE - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        if body.len() == MAX_ROBOTS_SIZE {
+            break;
+        }
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allows(rules: &RobotsRules, path: &str) -> bool {
+        rules.allows(&Url::parse("https://site.test").unwrap().join(path).unwrap())
+    }
+
+    #[test]
+    fn groups_are_matched_on_the_whole_product_token() {
+        let text = "User-agent: hex\nDisallow: /hex\n\nUser-agent: *\nDisallow: /all\n";
+        let rules = RobotsRules::parse(text, "hexichor");
+        assert!(allows(&rules, "/hex"));
+        assert!(!allows(&rules, "/all"));
+    }
+
+    #[test]
+    fn product_tokens_are_matched_whatever_their_case_and_version() {
+        for agent in ["Hexichor", "HEXICHOR/0.1"] {
+            let text = format!("User-agent: {agent}\nDisallow: /ours\n\nUser-agent: *\nDisallow: /\n");
+            let rules = RobotsRules::parse(&text, "hexichor");
+            assert!(!allows(&rules, "/ours"));
+            assert!(allows(&rules, "/other"));
+        }
+    }
+
+    #[tokio::test]
+    async fn turns_are_booked_one_delay_apart() {
+        let robots = RobotsCache::new("hexichor/0.1");
+        let url = Url::parse("https://site.test/page").unwrap();
+        let other = Url::parse("https://other.test/page").unwrap();
+        let delay = Duration::from_secs(5);
+        let first = robots.book_turn(&url, delay).await;
+        assert!(first <= Instant::now());
+        assert_eq!(robots.book_turn(&url, delay).await, first + delay);
+        assert!(robots.book_turn(&other, delay).await <= Instant::now());
+    }
+
+    #[tokio::test]
+    async fn turns_that_have_passed_are_forgotten() {
+        let robots = RobotsCache::new("hexichor/0.1");
+        let url = Url::parse("https://site.test/page").unwrap();
+        let other = Url::parse("https://other.test/page").unwrap();
+        robots.book_turn(&url, Duration::from_millis(10)).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        robots.book_turn(&other, Duration::from_secs(5)).await;
+        let booked: Vec<String> = robots.next_turn.lock().await.keys().cloned().collect();
+        assert_eq!(booked, vec![other.origin().ascii_serialization()]);
+    }
+}
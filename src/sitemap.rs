@@ -0,0 +1,192 @@
+//! Sitemaps
+//!
+//! A sitemap lists the pages of a site, so a job can start from it rather
+//! than from a list made by hand. Sitemaps come as XML, possibly gzipped,
+//! or as plain text with one URL per line. Sitemap indexes list other
+//! sitemaps, which are then read in turn.
+
+use flate2::read::GzDecoder;
+use quick_xml::{
+    events::Event,
+    Reader
+};
+use reqwest::Url;
+
+use std::io::Read;
+
+use crate::links::Link;
+
+/// Most a sitemap can weigh once decompressed, as the protocol says
+pub const MAX_SITEMAP_SIZE: usize = 50 * 1024 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// What is being read inside an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Other,
+    Loc,
+    Lastmod
+}
+
+/// Undo the compression of `body`, if any
+fn decompress(body: &[u8]) -> Result<Vec<u8>, String> {
+    if !body.starts_with(&GZIP_MAGIC) {
+        return Ok(body.to_vec());
+    }
+    let mut inflated = Vec::new();
+    GzDecoder::new(body)
+        .take(MAX_SITEMAP_SIZE as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| format!("invalid gzip data: {e}"))?;
+    if inflated.len() > MAX_SITEMAP_SIZE {
+        return Err(format!("larger than {MAX_SITEMAP_SIZE} bytes once decompressed"));
+    }
+    Ok(inflated)
+}
+
+/// The pages and sitemaps listed in `body`, fetched from `sitemap`
+pub fn parse(body: &[u8], sitemap: &Url) -> Result<Vec<Link>, String> {
+    let body = decompress(body)?;
+    let text = String::from_utf8_lossy(&body);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if !text.starts_with('<') {
+        return Ok(text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| sitemap.join(line).ok())
+            .map(Link::new)
+            .collect());
+    }
+
+    let mut reader = Reader::from_str(text);
+    let mut links = Vec::new();
+    // Whether the entry is a sitemap, its location and last modification
+    let mut entry: Option<(bool, String, Option<String>)> = None;
+    let mut field = Field::Other;
+    loop {
+        let event = reader.read_event()
+            .map_err(|e| format!("invalid XML at byte {}: {e}", reader.buffer_position()))?;
+        match event {
+            Event::Start(tag) => match tag.local_name().as_ref() {
+                b"url" => entry = Some((false, String::new(), None)),
+                b"sitemap" => entry = Some((true, String::new(), None)),
+                b"loc" => field = Field::Loc,
+                b"lastmod" => field = Field::Lastmod,
+                _ => field = Field::Other
+            },
+            Event::Text(text) => if let Some((_, loc, lastmod)) = &mut entry {
+                let text = text.unescape()
+                    .map_err(|e| format!("invalid XML at byte {}: {e}", reader.buffer_position()))?;
+                match field {
+                    Field::Loc => loc.push_str(&text),
+                    Field::Lastmod => lastmod.get_or_insert_with(String::new).push_str(&text),
+                    Field::Other => {}
+                }
+            },
+            Event::CData(data) => if let (Some((_, loc, _)), Field::Loc) = (&mut entry, field) {
+                loc.push_str(&String::from_utf8_lossy(&data));
+            },
+            Event::End(tag) => match tag.local_name().as_ref() {
+                b"url" | b"sitemap" => if let Some((is_sitemap, loc, lastmod)) = entry.take() {
+                    if let Ok(url) = sitemap.join(loc.trim()) {
+                        links.push(Link::new(url)
+                            .with_lastmod(lastmod.map(|lastmod| lastmod.trim().to_string()))
+                            .with_sitemap(is_sitemap));
+                    }
+                },
+                _ => field = Field::Other
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::{
+        write::GzEncoder,
+        Compression
+    };
+
+    use std::io::Write;
+
+    fn base() -> Url {
+        Url::parse("https://site.test/sitemaps/main.xml").unwrap()
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn entries(links: Vec<Link>) -> Vec<(bool, String, Option<String>)> {
+        links.into_iter()
+            .map(|link| {
+                let sitemap = link.is_sitemap();
+                let (url, lastmod) = link.explode();
+                (sitemap, url.to_string(), lastmod)
+            })
+            .collect()
+    }
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://site.test/</loc><lastmod> 2024-01-02 </lastmod></url>
+  <url><loc>/about?a=1&amp;b=2</loc></url>
+  <url><loc><![CDATA[https://site.test/cdata]]></loc></url>
+</urlset>"#;
+
+    #[test]
+    fn pages_of_a_urlset() {
+        assert_eq!(entries(parse(URLSET.as_bytes(), &base()).unwrap()), [
+            (false, String::from("https://site.test/"), Some(String::from("2024-01-02"))),
+            (false, String::from("https://site.test/about?a=1&b=2"), None),
+            (false, String::from("https://site.test/cdata"), None)
+        ]);
+    }
+
+    #[test]
+    fn gzipped_sitemaps_are_inflated() {
+        let body = gzip(URLSET.as_bytes());
+        assert_eq!(parse(&body, &base()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn sitemaps_of_an_index() {
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>pages.xml.gz</loc><lastmod>2024-03-04</lastmod></sitemap>
+  <sitemap><loc>https://site.test/posts.xml</loc></sitemap>
+</sitemapindex>"#;
+        assert_eq!(entries(parse(&gzip(index.as_bytes()), &base()).unwrap()), [
+            (true, String::from("https://site.test/sitemaps/pages.xml.gz"), Some(String::from("2024-03-04"))),
+            (true, String::from("https://site.test/posts.xml"), None)
+        ]);
+    }
+
+    #[test]
+    fn text_sitemaps_list_one_url_per_line() {
+        let body = "\u{feff}https://site.test/a\n\n  https://site.test/b  \n";
+        let urls: Vec<String> = entries(parse(body.as_bytes(), &base()).unwrap())
+            .into_iter()
+            .map(|(_, url, _)| url)
+            .collect();
+        assert_eq!(urls, ["https://site.test/a", "https://site.test/b"]);
+    }
+
+    #[test]
+    fn broken_gzip_is_an_error() {
+        let mut body = gzip(URLSET.as_bytes());
+        body.truncate(body.len() / 2);
+        assert!(parse(&body, &base()).unwrap_err().starts_with("invalid gzip data"));
+    }
+
+    #[test]
+    fn broken_xml_is_an_error() {
+        assert!(parse(b"<urlset><url></urlset>", &base()).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Soft 404 detection
+//!
+//! Some sites answer `200 OK` with a "page not found" page. To spot them,
+//! we ask every host for a path that cannot exist and compare what we get
+//! for real URLs against that baseline.
+
+use rand::{
+    distributions::Alphanumeric,
+    Rng
+};
+use regex::Regex;
+use reqwest::Url;
+use tokio::sync::{
+    Mutex,
+    OnceCell
+};
+use warp::http::StatusCode;
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet
+    },
+    sync::{
+        Arc,
+        OnceLock
+    }
+};
+
+/// How close to the baseline a page has to be to be called a soft 404
+const SIMILARITY_THRESHOLD: f64 = 0.9;
+const LENGTH_THRESHOLD: f64 = 0.9;
+
+fn title_regex() -> &'static Regex {
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    TITLE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap())
+}
+
+fn markup_regex() -> &'static Regex {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    MARKUP.get_or_init(|| Regex::new(r"(?is)<script.*?</script>|<style.*?</style>|<[^>]*>").unwrap())
+}
+
+/// What a page looks like, as far as the comparison goes
+#[derive(Debug)]
+pub struct PageSummary {
+    title: Option<String>,
+    length: usize,
+    tokens: HashSet<String>
+}
+
+impl PageSummary {
+    pub fn new(body: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(body);
+        let title = title_regex()
+            .captures(&text)
+            .map(|c| c[1].trim().to_lowercase());
+        let tokens = markup_regex()
+            .replace_all(&text, " ")
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        Self { title, length: body.len(), tokens }
+    }
+
+    /// Jaccard index of the words of both pages
+    #[allow(clippy::cast_precision_loss)]
+    fn similarity(&self, other: &Self) -> f64 {
+        let union = self.tokens.union(&other.tokens).count();
+        if union == 0 {
+            return 1.0;
+        }
+        self.tokens.intersection(&other.tokens).count() as f64 / union as f64
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn length_ratio(&self, other: &Self) -> f64 {
+        let (small, big) = if self.length < other.length {
+            (self.length, other.length)
+        } else {
+            (other.length, self.length)
+        };
+        if big == 0 {
+            return 1.0;
+        }
+        small as f64 / big as f64
+    }
+}
+
+/// The answer of a host to a path that does not exist
+#[derive(Debug)]
+pub struct Baseline {
+    status: StatusCode,
+    page: PageSummary
+}
+
+impl Baseline {
+    pub const fn new(status: StatusCode, page: PageSummary) -> Self {
+        Self { status, page }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Soft404Report {
+    likely: bool,
+    similarity: f64,
+    same_title: bool,
+    length_ratio: f64
+}
+
+impl Soft404Report {
+    pub fn compare(baseline: &Baseline, page: &PageSummary) -> Self {
+        let similarity = page.similarity(&baseline.page);
+        let same_title = page.title.is_some() && page.title == baseline.page.title;
+        let length_ratio = page.length_ratio(&baseline.page);
+        // A host that answers real 404s does not serve soft ones
+        let likely = baseline.status.is_success() && (
+            similarity >= SIMILARITY_THRESHOLD
+            || (same_title && length_ratio >= LENGTH_THRESHOLD)
+        );
+        Self { likely, similarity, same_title, length_ratio }
+    }
+
+    pub const fn is_likely(&self) -> bool {
+        self.likely
+    }
+
+    pub const fn get_similarity(&self) -> f64 {
+        self.similarity
+    }
+
+    pub const fn has_same_title(&self) -> bool {
+        self.same_title
+    }
+
+    pub const fn get_length_ratio(&self) -> f64 {
+        self.length_ratio
+    }
+}
+
+type BaselineCell = Arc<OnceCell<Option<Arc<Baseline>>>>;
+
+/// Baselines of every host met during a job
+#[derive(Debug, Default)]
+pub struct Soft404Detector {
+    baselines: Mutex<HashMap<String, BaselineCell>>
+}
+
+impl Soft404Detector {
+    /// A URL on the same host as `url` that should not exist
+    pub fn probe_url(url: &Url) -> Url {
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+        let mut probe = url.clone();
+        probe.set_path(&format!("/{random}"));
+        probe.set_query(None);
+        probe.set_fragment(None);
+        probe
+    }
+
+    /// Get the baseline of the host of `url`, computing it with
+    /// `fetch_baseline` if nobody did it yet
+    pub async fn baseline<F, Fut>(&self, url: &Url, fetch_baseline: F) -> Option<Arc<Baseline>>
+    where
+        F: FnOnce(Url) -> Fut,
+        Fut: std::future::Future<Output = Option<Baseline>>
+    {
+        let origin = url.origin().ascii_serialization();
+        let cell = Arc::clone(self.baselines.lock().await
+            .entry(origin)
+            .or_default());
+        cell.get_or_init(|| async {
+            fetch_baseline(Self::probe_url(url)).await.map(Arc::new)
+        }).await.clone()
+    }
+}
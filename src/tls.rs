@@ -0,0 +1,182 @@
+//! Certificate inspection
+//!
+//! The certificates a server presents are looked at during the handshake
+//! of the connection a fetch goes through, see `connector`. A chain that
+//! cannot be verified fails the fetch, unless the job is insecure, but it
+//! is reported all the same so that we can tell what is wrong with it.
+
+use chrono::{
+    DateTime,
+    Duration as ChronoDuration,
+    SubsecRound,
+    Utc
+};
+use openssl::{
+    asn1::{
+        Asn1Time,
+        Asn1TimeRef
+    },
+    ssl::SslRef,
+    x509::{
+        X509NameRef,
+        X509Ref,
+        X509VerifyResult
+    }
+};
+
+use std::net::IpAddr;
+
+/// `CN=example.com, O=Example` and so on
+fn describe_name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| format!(
+            "{}={}",
+            entry.object().nid().short_name().unwrap_or("?"),
+            entry.data().to_string().unwrap_or_default()
+        ))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn to_datetime(time: &Asn1TimeRef) -> Option<DateTime<Utc>> {
+    let diff = Asn1Time::days_from_now(0).ok()?.diff(time).ok()?;
+    let now = Utc::now().trunc_subsecs(0);
+    Some(now
+        + ChronoDuration::days(diff.days.into())
+        + ChronoDuration::seconds(diff.secs.into()))
+}
+
+#[derive(Debug, Clone)]
+pub struct CertInfo {
+    subject: String,
+    issuer: String,
+    sans: Vec<String>,
+    not_after: Option<DateTime<Utc>>
+}
+
+impl CertInfo {
+    fn new(cert: &X509Ref) -> Self {
+        let sans = cert.subject_alt_names()
+            .map(|names| names.iter()
+                .filter_map(|name| name.dnsname().map(String::from).or_else(|| {
+                    let ip: IpAddr = match name.ipaddress()? {
+                        &[a, b, c, d] => [a, b, c, d].into(),
+                        bytes => <[u8; 16]>::try_from(bytes).ok()?.into()
+                    };
+                    Some(ip.to_string())
+                }))
+                .collect())
+            .unwrap_or_default();
+        Self {
+            subject: describe_name(cert.subject_name()),
+            issuer: describe_name(cert.issuer_name()),
+            sans,
+            not_after: to_datetime(cert.not_after())
+        }
+    }
+
+    /// Whether the certificate was issued for `host`
+    fn matches(&self, host: &str, common_name: Option<&str>) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let matches_pattern = |pattern: &str| {
+            let pattern = pattern.to_ascii_lowercase();
+            // A wildcard only stands for a single label
+            pattern.strip_prefix("*.").map_or(pattern == host, |domain| {
+                host.split_once('.').is_some_and(|(label, rest)| !label.is_empty() && rest == domain)
+            })
+        };
+        // The common name is only looked at by old clients, and
+        // only when the certificate has no alternative names
+        if self.sans.is_empty() {
+            return common_name.is_some_and(matches_pattern);
+        }
+        self.sans.iter().any(|san| matches_pattern(san))
+    }
+
+    pub fn get_subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn get_issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn get_sans(&self) -> &[String] {
+        &self.sans
+    }
+
+    pub const fn get_not_after(&self) -> Option<DateTime<Utc>> {
+        self.not_after
+    }
+}
+
+/// What we know of the certificates of a server
+#[derive(Debug, Clone)]
+pub struct TlsReport {
+    // The server that presented those certificates
+    address: IpAddr,
+    chain: Vec<CertInfo>,
+    hostname_match: bool,
+    verify_error: Option<String>,
+    expiring: bool
+}
+
+impl TlsReport {
+    /// Look at the certificates presented during the handshake of `ssl`,
+    /// made with the server at `address`
+    pub fn inspect(ssl: &SslRef, host: &str, address: IpAddr) -> Option<Self> {
+        // Once verification failed, only the chain is left
+        let leaf = ssl.peer_certificate()
+            .or_else(|| ssl.peer_cert_chain()?.get(0).map(ToOwned::to_owned))?;
+        let leaf_info = CertInfo::new(&leaf);
+        let common_name = leaf.subject_name()
+            .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok());
+        let hostname_match = leaf_info
+            .matches(host.trim_start_matches('[').trim_end_matches(']'), common_name.as_deref());
+        let chain: Vec<CertInfo> = match ssl.peer_cert_chain() {
+            Some(chain) if !chain.is_empty() => chain.iter().map(CertInfo::new).collect(),
+            _ => vec![leaf_info]
+        };
+        let verify = ssl.verify_result();
+        let verify_error = (verify != X509VerifyResult::OK)
+            .then(|| verify.error_string().to_string());
+        Some(Self { address, chain, hostname_match, verify_error, expiring: false })
+    }
+
+    /// Days before the first certificate of the chain expires,
+    /// negative if one already has
+    pub fn days_left(&self) -> Option<i64> {
+        let now = Utc::now();
+        self.chain.iter()
+            .filter_map(|cert| cert.not_after)
+            .min()
+            .map(|not_after| (not_after - now).num_days())
+    }
+
+    /// Flag the chain if it expires within `days`
+    pub fn set_warning(&mut self, days: u32) {
+        self.expiring = self.days_left().is_some_and(|left| left < days.into());
+    }
+
+    pub const fn get_address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn get_chain(&self) -> &[CertInfo] {
+        &self.chain
+    }
+
+    pub const fn has_hostname_match(&self) -> bool {
+        self.hostname_match
+    }
+
+    pub fn get_verify_error(&self) -> Option<&str> {
+        self.verify_error.as_deref()
+    }
+
+    pub const fn is_expiring(&self) -> bool {
+        self.expiring
+    }
+}
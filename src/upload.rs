@@ -0,0 +1,292 @@
+//! Uploaded URL lists
+//!
+//! Lists of URLs rarely start out as JSON: they are kept in text files,
+//! one per line, or exported from spreadsheets as CSV. Either can be sent
+//! as the body of a submission, or uploaded as a file, and is turned into
+//! the entries of a job here. Other columns of a CSV file are kept as
+//! labels, and come back with the results of their URL.
+
+use std::collections::HashMap;
+
+use crate::dto::UrlEntry;
+
+/// How the URLs of a submission are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Text,
+    Csv,
+    Multipart
+}
+
+/// The media type of a `Content-Type` header, without its parameters
+pub fn essence(content_type: &str) -> String {
+    content_type.split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+impl Format {
+    /// The format of a submission of type `content_type`. Bodies of any
+    /// other type are read as JSON, as they always were, and so are text
+    /// bodies that look like it, since browsers send strings as text.
+    pub fn of_body(content_type: Option<&str>, body: &[u8]) -> Self {
+        match content_type.map(essence).as_deref() {
+            Some("text/plain") if !looks_like_json(body) => Self::Text,
+            Some("text/csv") => Self::Csv,
+            Some("multipart/form-data") => Self::Multipart,
+            _ => Self::Json
+        }
+    }
+
+    /// The format of an uploaded file, going by its extension when
+    /// the client did not say
+    pub fn of_file(content_type: Option<&str>, file_name: Option<&str>) -> Option<Self> {
+        match content_type.map(essence).as_deref() {
+            Some("text/plain") => Some(Self::Text),
+            Some("text/csv") => Some(Self::Csv),
+            None | Some("application/octet-stream") => {
+                let csv = file_name.and_then(|name| name.rsplit_once('.'))
+                    .is_some_and(|(_, extension)| extension.eq_ignore_ascii_case("csv"));
+                Some(if csv { Self::Csv } else { Self::Text })
+            },
+            _ => None
+        }
+    }
+}
+
+fn looks_like_json(body: &[u8]) -> bool {
+    body.iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| matches!(byte, b'[' | b'{'))
+}
+
+/// Read `body` as UTF-8, without the byte order mark some editors add
+pub fn decode(body: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(body)
+        .map(|text| text.trim_start_matches('\u{feff}'))
+        .map_err(|e| format!("not valid UTF-8 ({e})"))
+}
+
+/// One URL per line. Blank lines and lines starting with `#` are skipped.
+pub fn parse_text(text: &str) -> Vec<UrlEntry> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| UrlEntry::Plain(line.into()))
+        .collect()
+}
+
+/// How a CSV file is laid out
+#[derive(Debug, Clone)]
+pub struct CsvLayout {
+    // The name or number, from 1, of the column holding URLs
+    column: Option<String>,
+    delimiter: char,
+    header: bool
+}
+
+impl Default for CsvLayout {
+    fn default() -> Self {
+        Self { column: None, delimiter: ',', header: true }
+    }
+}
+
+impl CsvLayout {
+    pub fn new(column: Option<&str>, delimiter: Option<&str>, header: Option<bool>) -> Result<Self, String> {
+        let delimiter = match delimiter {
+            None => ',',
+            Some(delimiter) => {
+                let mut chars = delimiter.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' && c != '\n' && c != '\r' => c,
+                    _ => return Err(format!("invalid CSV delimiter \"{delimiter}\""))
+                }
+            }
+        };
+        Ok(Self {
+            column: column.map(String::from),
+            delimiter,
+            header: header.unwrap_or(true)
+        })
+    }
+
+    /// Where URLs are among `names`, the columns of the header if any
+    fn url_index(&self, names: Option<&[String]>) -> Result<usize, String> {
+        let by_name = |wanted: &str| names.and_then(|names| names.iter()
+            .position(|name| name.trim().eq_ignore_ascii_case(wanted)));
+        self.column.as_ref().map_or_else(
+            || Ok(by_name("url").unwrap_or(0)),
+            |column| by_name(column)
+                .or_else(|| column.parse::<usize>().ok()
+                    .filter(|number| *number > 0)
+                    .map(|number| number - 1))
+                .ok_or_else(|| format!("no column \"{column}\" in the CSV header"))
+        )
+    }
+}
+
+/// The records of `text`, along with the line each starts on. Fields may
+/// be quoted, doubling the quotes they hold, and then span several lines.
+fn records(text: &str, delimiter: char) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let (mut line, mut start) = (1, 1);
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            },
+            c => field.push(c)
+        }
+    }
+    if quoted {
+        return Err(format!("unterminated quote in the record on line {start}"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+/// One URL per record, in the column `layout` points at. The other
+/// columns become labels, named after the header or numbered from 1.
+pub fn parse_csv(text: &str, layout: &CsvLayout) -> Result<Vec<UrlEntry>, String> {
+    let mut records = records(text, layout.delimiter)?.into_iter()
+        // Blank lines are not records
+        .filter(|(_, fields)| fields.iter().any(|field| !field.trim().is_empty()));
+    let names = if layout.header {
+        records.next().map(|(_, names)| names)
+    } else {
+        None
+    };
+    let index = layout.url_index(names.as_deref())?;
+    let name = |i: usize| names.as_ref()
+        .and_then(|names| names.get(i))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| (i + 1).to_string());
+    records
+        .map(|(line, fields)| {
+            let url = fields.get(index)
+                .map(|url| url.trim())
+                .filter(|url| !url.is_empty())
+                .ok_or_else(|| format!("no URL in the record on line {line}"))?;
+            let labels: HashMap<String, String> = fields.iter()
+                .enumerate()
+                .filter(|(i, value)| *i != index && !value.is_empty())
+                .map(|(i, value)| (name(i), value.clone()))
+                .collect();
+            Ok(UrlEntry::labelled(url.into(), labels))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(entry: &UrlEntry) -> Vec<(&str, &str)> {
+        let mut labels: Vec<(&str, &str)> = entry.get_labels()
+            .into_iter()
+            .flatten()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        labels.sort_unstable();
+        labels
+    }
+
+    #[test]
+    fn quoted_fields_hold_delimiters_quotes_and_newlines() {
+        let text = "url,title\n\"https://a.test/?x=1,2\",\"Say \"\"hi\"\",\nthen go\"\n";
+        let entries = parse_csv(text, &CsvLayout::default()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].get_url(), "https://a.test/?x=1,2");
+        assert_eq!(labels(&entries[0]), [("title", "Say \"hi\",\nthen go")]);
+    }
+
+    #[test]
+    fn records_are_numbered_by_their_first_line() {
+        let records = records("a,\"b\nc\"\nd,e", ',').unwrap();
+        assert_eq!(records, [
+            (1, vec![String::from("a"), String::from("b\nc")]),
+            (3, vec![String::from("d"), String::from("e")])
+        ]);
+    }
+
+    #[test]
+    fn crlf_ends_records() {
+        let text = "url;note\r\nhttps://a.test/;one\r\n\r\nhttps://b.test/;two\r\n";
+        let layout = CsvLayout::new(None, Some(";"), None).unwrap();
+        let entries = parse_csv(text, &layout).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].get_url(), "https://a.test/");
+        assert_eq!(labels(&entries[0]), [("note", "one")]);
+        assert_eq!(labels(&entries[1]), [("note", "two")]);
+    }
+
+    #[test]
+    fn quoted_crlf_is_kept() {
+        let records = records("\"a\r\nb\"\r\n", ',').unwrap();
+        assert_eq!(records, [(1, vec![String::from("a\r\nb")])]);
+    }
+
+    #[test]
+    fn missing_columns_are_left_out_of_labels() {
+        let text = "name,url,note\nfirst,https://a.test/\n";
+        let entries = parse_csv(text, &CsvLayout::default()).unwrap();
+        assert_eq!(entries[0].get_url(), "https://a.test/");
+        assert_eq!(labels(&entries[0]), [("name", "first")]);
+    }
+
+    #[test]
+    fn a_missing_url_column_is_an_error() {
+        let text = "name,url\nfirst,https://a.test/\nsecond\n";
+        let error = parse_csv(text, &CsvLayout::default()).unwrap_err();
+        assert_eq!(error, "no URL in the record on line 3");
+    }
+
+    #[test]
+    fn columns_are_found_by_name_or_number() {
+        let text = "https://a.test/,Home\n";
+        let layout = CsvLayout::new(Some("1"), None, Some(false)).unwrap();
+        let entries = parse_csv(text, &layout).unwrap();
+        assert_eq!(labels(&entries[0]), [("2", "Home")]);
+        let layout = CsvLayout::new(Some("link"), None, None).unwrap();
+        assert!(parse_csv("url\nhttps://a.test/\n", &layout).is_err());
+    }
+
+    #[test]
+    fn unterminated_quotes_are_an_error() {
+        let error = records("url\n\"https://a.test/\n", ',').unwrap_err();
+        assert_eq!(error, "unterminated quote in the record on line 2");
+    }
+}
@@ -0,0 +1,139 @@
+//! Validation of submitted URLs
+//!
+//! `Url::parse` happily accepts `mailto:` or `data:` URLs, which workers
+//! cannot do anything useful with. Everything that can be refused before
+//! a job starts is refused here, with a reason for every bad entry.
+//!
+//! URLs that are written differently but point to the same resource are
+//! normalized to a single form, so that a job only fetches them once.
+
+use reqwest::Url;
+
+use std::sync::Arc;
+
+use crate::egress::EgressPolicy;
+
+/// Room for the labels and expectations of an entry, or the
+/// markup around a link in a document
+const ENTRY_OVERHEAD: usize = 1024;
+/// Room for the options of a job
+const OPTIONS_OVERHEAD: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct UrlPolicy {
+    schemes: Vec<String>,
+    max_url_length: usize,
+    max_urls: usize,
+    // Addresses given by jobs are held to it as soon as they are submitted
+    egress: Arc<EgressPolicy>
+}
+
+impl UrlPolicy {
+    pub fn new(schemes: Vec<String>, max_url_length: usize, max_urls: usize) -> Self {
+        Self {
+            schemes: schemes.into_iter().map(|s| s.to_ascii_lowercase()).collect(),
+            max_url_length,
+            max_urls,
+            egress: Arc::default()
+        }
+    }
+
+    pub fn with_egress(mut self, egress: Arc<EgressPolicy>) -> Self {
+        self.egress = egress;
+        self
+    }
+
+    pub fn get_egress(&self) -> &EgressPolicy {
+        &self.egress
+    }
+
+    pub const fn get_max_urls(&self) -> usize {
+        self.max_urls
+    }
+
+    /// The largest body a submission can have: as many URLs as a job can
+    /// hold, each with room for what comes along with it, and the options
+    pub const fn get_max_body(&self) -> u64 {
+        (self.max_urls * (self.max_url_length + ENTRY_OVERHEAD) + OPTIONS_OVERHEAD) as u64
+    }
+
+    /// Parse `raw`, or tell why it cannot be fetched
+    pub fn validate(&self, raw: &str) -> Result<Url, String> {
+        if raw.len() > self.max_url_length {
+            return Err(format!("longer than {} bytes", self.max_url_length));
+        }
+        let url = Url::parse(raw)
+            .map_err(|e| format!("not a valid URL ({e})"))?;
+        if !self.schemes.iter().any(|s| s == url.scheme()) {
+            return Err(format!(
+                "scheme \"{}\" is not one of {}",
+                url.scheme(), self.schemes.join(", ")
+            ));
+        }
+        if url.host_str().is_none_or(str::is_empty) {
+            return Err(String::from("no host"));
+        }
+        Ok(url)
+    }
+}
+
+/// Put `url` in a canonical form. The parser already lowercases the
+/// scheme and host and drops default ports.
+pub fn normalize(mut url: Url, sort_query: bool) -> Url {
+    if let Some(host) = url.host_str().filter(|h| h.ends_with('.')) {
+        let host = host.trim_end_matches('.').to_string();
+        // Only fails for hosts that cannot be empty, which
+        // a host made only of dots cannot pass validation for
+        if url.set_host(Some(&host)).is_err() {
+            return url;
+        }
+    }
+    // Fragments never reach the server
+    url.set_fragment(None);
+    if sort_query {
+        // Segments are sorted as they are written, decoding them would
+        // change what the server gets (`%20` would come back as `+`).
+        // Values of the same parameter keep their order.
+        let mut segments: Vec<&str> = url.query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+        segments.sort_by_key(|segment| segment.split('=').next().unwrap_or_default());
+        let query = segments.join("&");
+        url.set_query((!query.is_empty()).then_some(query.as_str()));
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(raw: &str) -> String {
+        normalize(Url::parse(raw).unwrap(), true).to_string()
+    }
+
+    #[test]
+    fn queries_are_sorted_without_being_decoded() {
+        assert_eq!(normalized("https://a.test/?q=a%20b&b=x+y&a=%2F"), "https://a.test/?a=%2F&b=x+y&q=a%20b");
+    }
+
+    #[test]
+    fn values_of_a_parameter_keep_their_order() {
+        assert_eq!(normalized("https://a.test/?id=2&b&id=1"), "https://a.test/?b&id=2&id=1");
+    }
+
+    #[test]
+    fn empty_queries_are_dropped() {
+        assert_eq!(normalized("https://a.test/?"), "https://a.test/");
+        assert_eq!(normalized("https://a.test/?&&"), "https://a.test/");
+        assert_eq!(normalized("https://a.test/?b=1&&a=2"), "https://a.test/?a=2&b=1");
+    }
+
+    #[test]
+    fn queries_are_left_alone_unless_asked() {
+        let url = normalize(Url::parse("https://a.test./?b=1&a=2#top").unwrap(), false);
+        assert_eq!(url.as_str(), "https://a.test/?b=1&a=2");
+    }
+}